@@ -0,0 +1,41 @@
+// An example showing how to convert between CSV and Password Safe databases.
+//
+// Run as: cargo run --example convert_csv import passwords.csv pwsafe.psafe3 password
+//     or: cargo run --example convert_csv export pwsafe.psafe3 password passwords.csv
+
+extern crate pwsafer;
+
+use pwsafer::{PwsafeDatabase, PwsafeWriter};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+
+fn import(csv: &str, filename: &str, password: &str) -> io::Result<()> {
+    let records = pwsafer::read_csv(BufReader::new(File::open(csv)?))?;
+    let file = BufWriter::new(File::create(filename)?);
+    let mut db = PwsafeWriter::new(file, 2048, password.as_bytes())?;
+    db.write_empty_header()?;
+    for record in &records {
+        db.write_record(record)?;
+    }
+    db.finish()
+}
+
+fn export(filename: &str, password: &str, csv: &str) -> io::Result<()> {
+    let file = BufReader::new(File::open(filename)?);
+    let db = PwsafeDatabase::read(file, password.as_bytes())?;
+    pwsafer::write_csv(db.records(), BufWriter::new(File::create(csv)?))
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("import") if args.len() == 5 => import(&args[2], &args[3], &args[4]),
+        Some("export") if args.len() == 5 => export(&args[2], &args[3], &args[4]),
+        _ => {
+            eprintln!("usage: convert_csv import CSV DATABASE PASSWORD");
+            eprintln!("       convert_csv export DATABASE PASSWORD CSV");
+            Ok(())
+        }
+    }
+}
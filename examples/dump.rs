@@ -2,9 +2,9 @@
 //
 // Run as: cargo run --example dump ~/.pwsafe/pwsafe.psafe3 password
 
-extern crate pwsafe;
+extern crate pwsafer;
 
-use pwsafe::{PwsafeHeaderField, PwsafeReader, PwsafeRecordField};
+use pwsafer::PwsafeReader;
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
@@ -17,19 +17,12 @@ fn main() {
     let file = BufReader::new(File::open(filename).unwrap());
 
     let mut db = PwsafeReader::new(file, password.as_bytes()).unwrap();
-    db.read_version().unwrap();
 
-    loop {
-        let (field_type, field_data) = db.read_field().unwrap().unwrap();
-        let field = PwsafeHeaderField::new(field_type, field_data);
+    for field in db.typed_header_fields() {
         println!("{:?}", field);
-        if field_type == 0xff {
-            break;
-        }
     }
 
-    while let Some((field_type, field_data)) = db.read_field().unwrap() {
-        let field = PwsafeRecordField::new(field_type, field_data);
+    for field in db.typed_fields() {
         println!("{:?}", field);
     }
     db.verify().unwrap();
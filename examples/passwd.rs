@@ -1,24 +1,24 @@
 // An example showing how to change the password of the database.
 
-extern crate pwsafe;
+extern crate pwsafer;
 
-use pwsafe::{PwsafeReader, PwsafeWriter};
+use pwsafer::{PwsafeReader, PwsafeWriter};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter};
 
-fn main() {
+fn main() -> Result<(), io::Error> {
     let rfilename = "./tests/pwsafe.psafe3";
     let wfilename = "./tests/pwsafe.new.psafe3";
 
-    let rfile = BufReader::new(File::open(rfilename).unwrap());
-    let wfile = BufWriter::new(File::create(wfilename).unwrap());
+    let rfile = BufReader::new(File::open(rfilename)?);
+    let wfile = BufWriter::new(File::create(wfilename)?);
 
-    let mut rdb = PwsafeReader::new(rfile, b"password").unwrap();
-    let mut wdb = PwsafeWriter::new(wfile, rdb.get_iter(), b"test").unwrap();
+    let mut rdb = PwsafeReader::new(rfile, b"password")?;
+    let mut wdb = PwsafeWriter::new(wfile, rdb.get_iter(), b"test")?;
 
-    while let Some((field_type, field_data)) = rdb.read_field().unwrap() {
-        wdb.write_field(field_type, &field_data).unwrap();
+    while let Some((field_type, field_data)) = rdb.read_field()? {
+        wdb.write_field(field_type, &field_data)?;
     }
-    rdb.verify().unwrap();
-    wdb.finish().unwrap();
+    rdb.verify()?;
+    wdb.finish()
 }
@@ -0,0 +1,41 @@
+//! RFC 4648 base32 as used for TOTP secrets.
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes bytes without padding.
+pub fn encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | u16::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            res.push(ALPHABET[usize::from((buffer >> bits) & 0x1f)] as char);
+        }
+    }
+    if bits > 0 {
+        res.push(ALPHABET[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+    }
+    res
+}
+
+/// Decodes base32, ignoring case, whitespace and padding.
+///
+/// Returns `None` on characters outside of the alphabet.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for c in s.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buffer >> bits) as u8);
+        }
+    }
+    Some(res)
+}
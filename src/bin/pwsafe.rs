@@ -0,0 +1,156 @@
+// Command line tool to inspect and convert Password Safe databases.
+//
+// Build with: cargo build --features cli
+
+extern crate pwsafer;
+extern crate rpassword;
+
+use pwsafer::{PwsafeDatabase, PwsafeReader, PwsafeRecordField, PwsafeWriter};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "usage: pwsafe dump DATABASE
+       pwsafe verify DATABASE
+       pwsafe passwd DATABASE
+       pwsafe export-csv DATABASE CSV
+       pwsafe import-csv CSV DATABASE
+
+export-csv writes all passwords in plaintext to CSV, readable only by the owner on Unix.";
+
+/// Iteration count for new databases.
+const ITER: u32 = 2048;
+
+/// Reads a password from the terminal without echo.
+fn prompt_password(prompt: &str) -> io::Result<String> {
+    rpassword::prompt_password(format!("{}: ", prompt))
+}
+
+/// Reads a new password twice, failing if the two do not match.
+fn prompt_new_password() -> io::Result<String> {
+    let password = prompt_password("New password")?;
+    if prompt_password("Repeat new password")? != password {
+        return Err(io::Error::other("Passwords do not match"));
+    }
+    Ok(password)
+}
+
+fn open(filename: &str) -> io::Result<PwsafeReader<BufReader<File>>> {
+    let file = BufReader::new(File::open(filename)?);
+    let password = prompt_password("Password")?;
+    Ok(PwsafeReader::new(file, password.as_bytes())?)
+}
+
+fn dump(filename: &str) -> io::Result<()> {
+    let mut db = open(filename)?;
+    for field in db.typed_header_fields() {
+        println!("{}", field?);
+    }
+    for field in db.typed_fields() {
+        match field? {
+            PwsafeRecordField::EndOfRecord => println!(),
+            field => println!("{}", field),
+        }
+    }
+    Ok(db.verify()?)
+}
+
+fn verify(filename: &str) -> io::Result<()> {
+    let file = BufReader::new(File::open(filename)?);
+    let password = prompt_password("Password")?;
+    let report = pwsafer::scan(file, password.as_bytes())?;
+    match report.version {
+        Some(version) => println!("Version: {:#06x}", version),
+        None => println!("Version: missing"),
+    }
+    println!("Records: {}", report.record_count);
+    println!("Unknown field types: {:02x?}", report.unknown_field_types);
+    println!("HMAC: {}", if report.hmac_valid { "valid" } else { "INVALID" });
+    for error in &report.parse_errors {
+        println!("Error: {}", error);
+    }
+    if !report.is_ok() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn passwd(filename: &str) -> io::Result<()> {
+    let mut rdb = open(filename)?;
+    let password = prompt_new_password()?;
+
+    // Write to a temporary file first, the database is replaced only once the copy is complete
+    let tmpname = format!("{}.tmp", filename);
+    let res = (|| {
+        let file = BufWriter::new(File::create(&tmpname)?);
+        let mut wdb = PwsafeWriter::new(file, rdb.get_iter(), password.as_bytes())?;
+        wdb.copy_fields(&mut rdb)?;
+        wdb.finish()
+    })();
+    match res {
+        Ok(()) => fs::rename(&tmpname, filename),
+        Err(e) => {
+            let _ = fs::remove_file(&tmpname);
+            Err(e)
+        }
+    }
+}
+
+fn export_csv(filename: &str, csv: &str) -> io::Result<()> {
+    let file = BufReader::new(File::open(filename)?);
+    let password = prompt_password("Password")?;
+    let db = PwsafeDatabase::read(file, password.as_bytes())?;
+    let mut out = BufWriter::new(create_private(csv)?);
+    pwsafer::write_csv(db.records(), &mut out)?;
+    out.flush()
+}
+
+/// Creates or truncates a file only the owner can read, for decrypted output.
+fn create_private(filename: &str) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(filename)?;
+        // `mode` only applies to new files
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(filename)
+}
+
+fn import_csv(csv: &str, filename: &str) -> io::Result<()> {
+    let records = pwsafer::read_csv(BufReader::new(File::open(csv)?))?;
+    let password = prompt_new_password()?;
+    // Never overwrite an existing database
+    let file = OpenOptions::new().write(true).create_new(true).open(filename)?;
+    let mut db = PwsafeWriter::new(BufWriter::new(file), ITER, password.as_bytes())?;
+    db.write_empty_header()?;
+    for record in &records {
+        db.write_record(record)?;
+    }
+    db.finish()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let res = match (args.get(1).map(String::as_str), args.len()) {
+        (Some("dump"), 3) => dump(&args[2]),
+        (Some("verify"), 3) => verify(&args[2]),
+        (Some("passwd"), 3) => passwd(&args[2]),
+        (Some("export-csv"), 4) => export_csv(&args[2], &args[3]),
+        (Some("import-csv"), 4) => import_csv(&args[2], &args[3]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("pwsafe: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::field::PwsafeRecordField;
+use crate::record::{PwsafeRecord, PwsafeRecordBuilder};
+use std::io::{self, Read, Write};
+
+/// Columns written by `write_csv`, in order.
+const COLUMNS: [&str; 6] = ["Group", "Title", "Username", "Password", "URL", "Notes"];
+
+/// Reads records from CSV with a header row.
+///
+/// Recognized columns are `Group`, `Title`, `Username`, `Password`, `URL` and `Notes`, matched
+/// case-insensitively, others are ignored. A `Title` and a `Password` column are required. Every
+/// record gets a new UUID and timestamps, empty cells are left out. Quoting follows RFC 4180.
+pub fn read_csv<R: Read>(mut inner: R) -> io::Result<Vec<PwsafeRecord>> {
+    let mut data = String::new();
+    inner.read_to_string(&mut data)?;
+    let mut rows = parse(&data)?.into_iter();
+    let header = rows.next().unwrap_or_default();
+    let columns: Vec<Option<usize>> = COLUMNS
+        .iter()
+        .map(|name| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name)))
+        .collect();
+    if columns[1].is_none() || columns[3].is_none() {
+        return Err(invalid("CSV needs Title and Password columns"));
+    }
+
+    let mut records = Vec::new();
+    for row in rows {
+        let cell = |i: usize| columns[i].and_then(|c| row.get(c)).map(String::as_str).unwrap_or("");
+        let mut builder = PwsafeRecordBuilder::new().title(cell(1)).password(cell(3));
+        if !cell(0).is_empty() {
+            builder = builder.group(cell(0));
+        }
+        if !cell(2).is_empty() {
+            builder = builder.username(cell(2));
+        }
+        if !cell(4).is_empty() {
+            builder = builder.url(cell(4));
+        }
+        if !cell(5).is_empty() {
+            builder = builder.notes(cell(5));
+        }
+        records.push(builder.build().map_err(io::Error::from)?);
+    }
+    Ok(records)
+}
+
+/// Writes records as CSV with a header row, see `read_csv` for the columns.
+///
+/// Other fields are not exported. The output contains the passwords in plaintext.
+pub fn write_csv<W: Write>(records: &[PwsafeRecord], mut inner: W) -> io::Result<()> {
+    write_row(&mut inner, &COLUMNS)?;
+    for record in records {
+        let mut row = [""; 6];
+        for field in record.fields() {
+            match field {
+                PwsafeRecordField::Group(s) => row[0] = s,
+                PwsafeRecordField::Title(s) => row[1] = s,
+                PwsafeRecordField::Username(s) => row[2] = s,
+                PwsafeRecordField::Password(s) => row[3] = s,
+                PwsafeRecordField::Url(s) => row[4] = s,
+                PwsafeRecordField::Notes(s) => row[5] = s,
+                _ => (),
+            }
+        }
+        write_row(&mut inner, &row)?;
+    }
+    inner.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Splits CSV into rows of cells, skipping empty lines.
+fn parse(data: &str) -> io::Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => (),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            c => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(invalid("unterminated quote in CSV"));
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn write_row<W: Write>(inner: &mut W, cells: &[&str]) -> io::Result<()> {
+    let line: Vec<String> = cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect();
+    writeln!(inner, "{}", line.join(","))
+}
@@ -0,0 +1,302 @@
+use crate::field::{PwsafeHeaderField, PwsafeRecordField};
+use crate::group::GroupTree;
+use crate::header::PwsafeHeader;
+use crate::reader::{read_header_checked, PwsafeReader, PwsafeReaderBuilder, Result};
+use crate::record::{random_uuid, PwsafeRecord};
+use crate::wipe::{zeroize, zeroize_string};
+use crate::writer::{empty_header, PwsafeWriter};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Password Safe database held in memory.
+///
+/// ```rust
+/// use pwsafer::PwsafeDatabase;
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+/// let db = PwsafeDatabase::read(file, b"password").unwrap();
+/// for record in db.find("test") {
+///     println!("{:?}", record.title());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PwsafeDatabase {
+    header: PwsafeHeader,
+    records: Vec<PwsafeRecord>,
+    /// Key the record MACs are checked against by `save`, see `record_mac_key`
+    record_mac_key: [u8; 32],
+}
+
+impl Default for PwsafeDatabase {
+    fn default() -> Self {
+        PwsafeDatabase::new(PwsafeHeader::default(), Vec::new())
+    }
+}
+
+// The record MAC key is left out, databases with the same contents are equal
+impl PartialEq for PwsafeDatabase {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.records == other.records
+    }
+}
+
+impl Eq for PwsafeDatabase {}
+
+impl fmt::Debug for PwsafeDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PwsafeDatabase")
+            .field("header", &self.header)
+            .field("records", &self.records)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Largest iteration count `parse_database` accepts.
+const PARSE_MAX_ITERATIONS: u32 = 1 << 16;
+
+/// Fields matched by `PwsafeDatabase::find_with`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Match titles
+    pub title: bool,
+    /// Match usernames
+    pub username: bool,
+    /// Match URLs
+    pub url: bool,
+    /// Match group paths
+    pub group: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            title: true,
+            username: true,
+            url: true,
+            group: false,
+        }
+    }
+}
+
+/// Parses and verifies a whole database held in memory.
+///
+/// Single entry point for fuzzing: any input, however malformed, results in an error rather than
+/// a panic. Inputs declaring more than 65536 key stretching iterations fail with
+/// `ExcessiveIterations` before any stretching, so no input keeps it busy for long. Use
+/// `PwsafeDatabase::read` for real databases, which often use more iterations.
+pub fn parse_database(bytes: &[u8], password: &[u8]) -> Result<PwsafeDatabase> {
+    let reader = PwsafeReaderBuilder::new()
+        .max_iterations(PARSE_MAX_ITERATIONS)
+        .open(bytes, password)?;
+    PwsafeDatabase::from_reader(reader)
+}
+
+/// Returns the size in bytes of a database with the given header and records.
+///
+/// Exact for databases written by `PwsafeDatabase::save`: the 152 byte preamble, every field
+/// padded to 16 byte blocks including end of header and end of record fields, and the 48 byte
+/// EOF block and HMAC.
+pub fn estimate_size(header: &PwsafeHeader, records: &[PwsafeRecord]) -> usize {
+    let field_size = |len: usize| 16 + len.saturating_sub(11).div_ceil(16) * 16;
+    let header_size: usize = header.fields().iter().map(|f| field_size(f.to_field().1.len())).sum();
+    let records_size: usize = records
+        .iter()
+        .flat_map(|r| r.fields())
+        .map(|f| field_size(f.to_field().1.len()))
+        .sum();
+    152 + header_size + 16 + records_size + 16 * records.len() + 48
+}
+
+impl PwsafeDatabase {
+    /// Creates a database from a header and records.
+    pub fn new(header: PwsafeHeader, records: Vec<PwsafeRecord>) -> Self {
+        let mut record_mac_key = [0u8; 32];
+        OsRng.fill_bytes(&mut record_mac_key);
+        PwsafeDatabase {
+            header,
+            records,
+            record_mac_key,
+        }
+    }
+
+    /// Creates a new empty database, returns it together with its freshly generated UUID.
+    ///
+    /// The header holds the version and UUID `PwsafeWriter::write_empty_header` writes. Keep the
+    /// UUID to recognize the database later, e.g. in a sync index.
+    pub fn create() -> (Self, [u8; 16]) {
+        let uuid = random_uuid(&mut OsRng);
+        let mut fields = empty_header(uuid).to_vec();
+        fields.retain(|field| field != &PwsafeHeaderField::EndOfHeader);
+        (PwsafeDatabase::new(PwsafeHeader::new(fields), Vec::new()), uuid)
+    }
+
+    /// Reads a whole database and checks its integrity.
+    pub fn read<R: Read>(inner: R, password: &[u8]) -> Result<Self> {
+        PwsafeDatabase::from_reader(PwsafeReader::new(inner, password)?)
+    }
+
+    /// Reads the header and all records of an opened database and checks its integrity.
+    fn from_reader<R>(reader: PwsafeReader<R>) -> Result<Self> {
+        let record_mac_key = reader.record_mac_key();
+        let (header, mut reader) = read_header_checked(reader)?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.read_record()? {
+            records.push(record);
+        }
+        reader.verify()?;
+        Ok(PwsafeDatabase {
+            header,
+            records,
+            record_mac_key,
+        })
+    }
+
+    /// Writes the whole database with the given password and iteration count.
+    ///
+    /// Header and record fields are written in their current order, including unknown ones.
+    /// The header is written as is, so the version field is not updated. Record MACs that
+    /// verify under `record_mac_key` are replaced by ones under the key of the new file, see
+    /// `PwsafeRecord::set_record_mac`. Other MACs are written as they are and keep failing, so
+    /// tampering is not hidden by a save. Records changed after reading need a new MAC under
+    /// `record_mac_key` to keep a valid one.
+    pub fn save<W: Write>(&self, inner: W, password: &[u8], iter: u32) -> io::Result<()> {
+        let mut writer = PwsafeWriter::new(inner, iter, password)?;
+        let end_of_header = PwsafeHeaderField::EndOfHeader;
+        for field in self.header.fields().iter().chain(Some(&end_of_header)) {
+            let (field_type, data) = field.to_field();
+            writer.write_field(field_type, &data)?;
+        }
+        let end_of_record = PwsafeRecordField::EndOfRecord;
+        let record_mac_key = writer.record_mac_key();
+        for record in &self.records {
+            let rekeyed;
+            let record = if record.verify_record_mac(&self.record_mac_key) == Some(true) {
+                let mut copy = record.clone();
+                copy.set_record_mac(&record_mac_key);
+                rekeyed = copy;
+                &rekeyed
+            } else {
+                record
+            };
+            for field in record.fields().iter().chain(Some(&end_of_record)) {
+                let (field_type, data) = field.to_field();
+                writer.write_field(field_type, &data)?;
+            }
+        }
+        writer.finish()
+    }
+
+    /// Turns the database into a skeleton that can be shared, e.g. to debug a problem.
+    ///
+    /// Every record gets a fresh random UUID. Passwords, notes and credit card fields are
+    /// blanked, password history and two-factor keys are removed. Titles, usernames, URLs, groups
+    /// and the header are kept. The secrets are overwritten with zeros before they are dropped,
+    /// copies made by the caller are not touched. Valid record MACs are updated.
+    pub fn anonymize(&mut self) {
+        for record in &mut self.records {
+            let mac_valid = record.verify_record_mac(&self.record_mac_key) == Some(true);
+            let fields = record.fields_mut();
+            for field in fields.iter_mut() {
+                match field {
+                    PwsafeRecordField::Uuid(uuid) => *uuid = random_uuid(&mut OsRng),
+                    PwsafeRecordField::Password(s)
+                    | PwsafeRecordField::Notes(s)
+                    | PwsafeRecordField::CreditCardNumber(s)
+                    | PwsafeRecordField::CreditCardExpiration(s)
+                    | PwsafeRecordField::CreditCardVerifValue(s)
+                    | PwsafeRecordField::CreditCardPin(s)
+                    | PwsafeRecordField::PasswordHistory(s) => zeroize_string(s),
+                    PwsafeRecordField::TwoFactorKey(key) => {
+                        zeroize(key);
+                        key.clear();
+                    }
+                    _ => (),
+                }
+            }
+            fields.retain(|field| {
+                !matches!(
+                    field,
+                    PwsafeRecordField::PasswordHistory(_) | PwsafeRecordField::TwoFactorKey(_)
+                )
+            });
+            if mac_valid {
+                record.set_record_mac(&self.record_mac_key);
+            }
+        }
+    }
+
+    /// Returns the key of the record MACs of this database, see `PwsafeRecord::set_record_mac`.
+    ///
+    /// For a database read from a file it is the key of that file, otherwise a random one. Add
+    /// a MAC with it to records created or changed through `records_mut`, `save` only carries
+    /// over MACs that verify under this key.
+    pub fn record_mac_key(&self) -> [u8; 32] {
+        self.record_mac_key
+    }
+
+    /// Returns the header.
+    pub fn header(&self) -> &PwsafeHeader {
+        &self.header
+    }
+
+    /// Returns the header for modification.
+    pub fn header_mut(&mut self) -> &mut PwsafeHeader {
+        &mut self.header
+    }
+
+    /// Returns the records.
+    pub fn records(&self) -> &[PwsafeRecord] {
+        &self.records
+    }
+
+    /// Returns the records for modification.
+    pub fn records_mut(&mut self) -> &mut Vec<PwsafeRecord> {
+        &mut self.records
+    }
+
+    /// Assembles the group hierarchy from the record groups and the `EmptyGroups` header
+    /// fields.
+    ///
+    /// Records without group are in the root. Empty groups appear in the tree even though no
+    /// record refers to them.
+    pub fn groups(&self) -> GroupTree {
+        let mut root = GroupTree::default();
+        for field in self.header.fields() {
+            if let PwsafeHeaderField::EmptyGroups(path) = field {
+                root.get_or_insert(path);
+            }
+        }
+        for (i, record) in self.records.iter().enumerate() {
+            root.get_or_insert(record.group().unwrap_or("")).records.push(i);
+        }
+        root
+    }
+
+    /// Returns records whose title, username or URL contain `query`, ignoring case.
+    pub fn find(&self, query: &str) -> Vec<&PwsafeRecord> {
+        self.find_with(query, &SearchOptions::default())
+    }
+
+    /// Returns records where one of the fields selected by `options` contains `query`, ignoring
+    /// case.
+    pub fn find_with(&self, query: &str, options: &SearchOptions) -> Vec<&PwsafeRecord> {
+        let query = query.to_lowercase();
+        let matches = |value: Option<&str>| match value {
+            Some(value) => value.to_lowercase().contains(&query),
+            None => false,
+        };
+        self.records
+            .iter()
+            .filter(|record| {
+                (options.title && matches(record.title()))
+                    || (options.username && matches(record.username()))
+                    || (options.url && matches(record.url()))
+                    || (options.group && matches(record.group()))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,162 @@
+use crate::base32;
+use crate::field::{AnyField, PwsafeHeaderField, PwsafeRecordField};
+use std::fmt;
+
+/// Shown instead of sensitive field contents.
+const MASK: &str = "********";
+
+/// Timestamp in seconds since the Unix epoch, displayed as UTC date and time.
+struct Time(u32);
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0 as i64;
+        // Converts days since the epoch to a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = secs.div_euclid(86400) + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        let time = secs.rem_euclid(86400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60
+        )
+    }
+}
+
+/// UUID displayed in the usual hyphenated form.
+struct Uuid<'a>(&'a [u8; 16]);
+
+impl fmt::Display for Uuid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `value` or, unless the alternate flag is set, the mask.
+fn secret(f: &mut fmt::Formatter, label: &str, value: &dyn fmt::Display) -> fmt::Result {
+    if f.alternate() {
+        write!(f, "{}: {}", label, value)
+    } else {
+        write!(f, "{}: {}", label, MASK)
+    }
+}
+
+/// One line for user-facing output, e.g. `Database name: Private`.
+///
+/// See `Debug` for the raw contents.
+impl fmt::Display for PwsafeHeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PwsafeHeaderField::Version(v) => write!(f, "Version: {:#06x}", v),
+            PwsafeHeaderField::Uuid(uuid) => write!(f, "UUID: {}", Uuid(uuid)),
+            PwsafeHeaderField::Preferences(s) => write!(f, "Preferences: {}", s),
+            PwsafeHeaderField::TreeDisplayStatus(s) => write!(f, "Tree display status: {}", s),
+            PwsafeHeaderField::LastSaveTimestamp(t) => write!(f, "Last saved: {}", Time(*t)),
+            PwsafeHeaderField::LastSaveWho(s) => write!(f, "Last saved by: {}", s),
+            PwsafeHeaderField::LastSaveWhat(s) => write!(f, "Last saved with: {}", s),
+            PwsafeHeaderField::LastSaveUser(s) => write!(f, "Last saved by user: {}", s),
+            PwsafeHeaderField::LastSaveHost(s) => write!(f, "Last saved on host: {}", s),
+            PwsafeHeaderField::DatabaseName(s) => write!(f, "Database name: {}", s),
+            PwsafeHeaderField::DatabaseDescription(s) => write!(f, "Database description: {}", s),
+            PwsafeHeaderField::DatabaseFilters(s) => write!(f, "Filters: {}", s),
+            PwsafeHeaderField::RecentlyUsedEntries(s) => write!(f, "Recently used entries: {}", s),
+            PwsafeHeaderField::NamedPasswordPolicies(s) => write!(f, "Password policies: {}", s),
+            PwsafeHeaderField::EmptyGroups(s) => write!(f, "Empty group: {}", s),
+            PwsafeHeaderField::Yubico(s) => secret(f, "Yubico", s),
+            PwsafeHeaderField::LastMasterPasswordChange(t) => {
+                write!(f, "Master password changed: {}", Time(*t))
+            }
+            PwsafeHeaderField::EndOfHeader => write!(f, "End of header"),
+            PwsafeHeaderField::Blob { field_type, data } => {
+                write!(f, "Unknown field 0x{:02x}: {} bytes", field_type, data.len())
+            }
+        }
+    }
+}
+
+/// One line for user-facing output, e.g. `Title: Gmail` or `Created: 2021-03-14 09:26:53`.
+///
+/// Passwords, notes, password history, two-factor keys, QR codes and credit card details are
+/// masked. The alternate flag (`{:#}`) shows them. Times are UTC. See `Debug` for the raw
+/// contents.
+impl fmt::Display for PwsafeRecordField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PwsafeRecordField::Uuid(uuid) => write!(f, "UUID: {}", Uuid(uuid)),
+            PwsafeRecordField::Group(s) => write!(f, "Group: {}", s),
+            PwsafeRecordField::Title(s) => write!(f, "Title: {}", s),
+            PwsafeRecordField::Username(s) => write!(f, "Username: {}", s),
+            PwsafeRecordField::Notes(s) => secret(f, "Notes", s),
+            PwsafeRecordField::Password(s) => secret(f, "Password", s),
+            PwsafeRecordField::CreationTime(t) => write!(f, "Created: {}", Time(*t)),
+            PwsafeRecordField::PasswordModificationTime(t) => {
+                write!(f, "Password modified: {}", Time(*t))
+            }
+            PwsafeRecordField::LastAccessTime(t) => write!(f, "Last accessed: {}", Time(*t)),
+            PwsafeRecordField::PasswordExpiryTime(t) => write!(f, "Password expires: {}", Time(*t)),
+            PwsafeRecordField::LastModificationTime(t) => write!(f, "Last modified: {}", Time(*t)),
+            PwsafeRecordField::Url(s) => write!(f, "URL: {}", s),
+            PwsafeRecordField::Autotype(s) => write!(f, "Autotype: {}", s),
+            PwsafeRecordField::PasswordHistory(s) => secret(f, "Password history", s),
+            PwsafeRecordField::PasswordPolicy(s) => write!(f, "Password policy: {}", s),
+            PwsafeRecordField::PasswordExpiryInterval(days) => {
+                write!(f, "Password expiry interval: {} days", days)
+            }
+            PwsafeRecordField::RunCommand(s) => write!(f, "Run command: {}", s),
+            PwsafeRecordField::DoubleClickAction(a) => write!(f, "Double-click action: {}", a),
+            PwsafeRecordField::EmailAddress(s) => write!(f, "Email: {}", s),
+            PwsafeRecordField::ProtectedEntry(p) => {
+                write!(f, "Protected: {}", if *p != 0 { "yes" } else { "no" })
+            }
+            PwsafeRecordField::OwnSymbolsForPassword(s) => write!(f, "Password symbols: {}", s),
+            PwsafeRecordField::ShiftDoubleClickAction(a) => {
+                write!(f, "Shift double-click action: {}", a)
+            }
+            PwsafeRecordField::PasswordPolicyName(s) => write!(f, "Password policy name: {}", s),
+            PwsafeRecordField::EntryKeyboardShortcut(k) => write!(f, "Keyboard shortcut: {:#010x}", k),
+            PwsafeRecordField::TwoFactorKey(key) => secret(f, "Two-factor key", &base32::encode(key)),
+            PwsafeRecordField::CreditCardNumber(s) => secret(f, "Credit card number", s),
+            PwsafeRecordField::CreditCardExpiration(s) => secret(f, "Credit card expiration", s),
+            PwsafeRecordField::CreditCardVerifValue(s) => secret(f, "Credit card verification value", s),
+            PwsafeRecordField::CreditCardPin(s) => secret(f, "Credit card PIN", s),
+            PwsafeRecordField::QrCode(s) => secret(f, "QR code", s),
+            PwsafeRecordField::TotpConfig(c) => write!(f, "TOTP configuration: {}", c),
+            PwsafeRecordField::TotpLength(n) => write!(f, "TOTP length: {} digits", n),
+            PwsafeRecordField::TotpTimeStep(s) => write!(f, "TOTP time step: {} seconds", s),
+            PwsafeRecordField::TotpStartTime(t) => write!(f, "TOTP start time: {}", Time(*t)),
+            PwsafeRecordField::EndOfRecord => write!(f, "End of record"),
+            PwsafeRecordField::Blob { field_type, data } => {
+                write!(f, "Unknown field 0x{:02x}: {} bytes", field_type, data.len())
+            }
+        }
+    }
+}
+
+/// Displays the wrapped field, see the header and record field implementations.
+impl fmt::Display for AnyField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnyField::Header(field) => field.fmt(f),
+            AnyField::Record(field) => field.fmt(f),
+        }
+    }
+}
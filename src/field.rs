@@ -1,4 +1,7 @@
+use crate::base32;
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::error;
 use std::fmt;
 use std::io;
 use std::io::Cursor;
@@ -9,21 +12,51 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 
 /// Password Safe field parsing error.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     /// Incorrect field length.
     InvalidLength,
+    /// Field contents do not follow the expected format.
+    InvalidFormat,
     /// An I/O error.
     IoError(io::Error),
     /// Error converting bytes to UTF-8 string.
     FromUtf8Error(string::FromUtf8Error),
+    /// A mandatory field is missing.
+    MissingField(RecordFieldType),
+    /// Timestamp is zero, too far in the future or inconsistent with other timestamps.
+    InvalidTimestamp(RecordFieldType),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::InvalidLength => write!(f, "Invalid field length"),
+            Error::InvalidFormat => write!(f, "Invalid field format"),
             Error::IoError(ref e) => e.fmt(f),
             Error::FromUtf8Error(ref e) => e.fmt(f),
+            Error::MissingField(field_type) => write!(f, "Missing {:?} field", field_type),
+            Error::InvalidTimestamp(field_type) => write!(f, "Invalid {:?} timestamp", field_type),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::IoError(ref e) => Some(e),
+            Error::FromUtf8Error(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Allows `?` on field results in code returning writer results.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::IoError(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
         }
     }
 }
@@ -40,6 +73,55 @@ impl From<string::FromUtf8Error> for Error {
     }
 }
 
+/// Prefix of compressed `Notes` fields, see the `compress` feature.
+#[cfg(feature = "compress")]
+const COMPRESSED_NOTES_MARKER: &[u8] = b"\0PWSZ";
+
+/// Shortest `Notes` compressed with the `compress` feature.
+#[cfg(feature = "compress")]
+const COMPRESS_NOTES_MIN_LEN: usize = 1024;
+
+/// Longest decompressed `Notes`, refuses compressed data that would exhaust memory.
+#[cfg(feature = "compress")]
+const MAX_NOTES_LEN: u64 = 1 << 24;
+
+/// Returns the marker followed by the zlib compressed notes.
+#[cfg(feature = "compress")]
+fn compress_notes(s: &str) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(COMPRESSED_NOTES_MARKER.to_vec(), Compression::default());
+    // Writing to a `Vec` does not fail
+    encoder.write_all(s.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompresses notes stored after the marker.
+#[cfg(feature = "compress")]
+fn decompress_notes(data: &[u8]) -> Result<String> {
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    let mut notes = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_NOTES_LEN + 1)
+        .read_to_end(&mut notes)
+        .map_err(|_| Error::InvalidFormat)?;
+    if notes.len() as u64 > MAX_NOTES_LEN {
+        return Err(Error::InvalidLength);
+    }
+    Ok(String::from_utf8(notes)?)
+}
+
+fn parse_u8(data: Vec<u8>) -> Result<u8> {
+    if data.len() != 1 {
+        return Err(Error::InvalidLength);
+    }
+    Ok(data[0])
+}
+
 fn parse_u16(data: Vec<u8>) -> Result<u16> {
     if data.len() != 2 {
         return Err(Error::InvalidLength);
@@ -58,10 +140,107 @@ fn parse_u32(data: Vec<u8>) -> Result<u32> {
     Ok(i)
 }
 
+/// Reads a number stored as `len` hexadecimal digits, as used by the text encoded fields.
+pub(crate) fn read_hex(chars: &mut std::str::Chars, len: usize) -> Result<u32> {
+    let digits: String = chars.take(len).collect();
+    if digits.len() != len || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidFormat);
+    }
+    u32::from_str_radix(&digits, 16).map_err(|_| Error::InvalidFormat)
+}
+
+/// Reserved header field types.
+const HEADER_RESERVED: [u8; 3] = [0x0c, 0x0d, 0x0e];
+
+/// Reserved record field types.
+const RECORD_RESERVED: [u8; 2] = [0x0b, 0x1a];
+
+/// Password Safe header field type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HeaderFieldType {
+    /// Version
+    Version = 0x00,
+    /// UUID
+    Uuid = 0x01,
+    /// Non-default preferences
+    Preferences = 0x02,
+    /// Tree Display Status
+    TreeDisplayStatus = 0x03,
+    /// Timestamp of last save
+    LastSaveTimestamp = 0x04,
+    /// Who performed last save
+    LastSaveWho = 0x05,
+    /// What performed last save
+    LastSaveWhat = 0x06,
+    /// Last saved by user
+    LastSaveUser = 0x07,
+    /// Last saved on host
+    LastSaveHost = 0x08,
+    /// Database Name
+    DatabaseName = 0x09,
+    /// Database Description
+    DatabaseDescription = 0x0a,
+    /// Database Filters
+    DatabaseFilters = 0x0b,
+    /// Recently Used Entries
+    RecentlyUsedEntries = 0x0f,
+    /// Named Password Policies
+    NamedPasswordPolicies = 0x10,
+    /// EmptyGroups
+    EmptyGroups = 0x11,
+    /// Yubico
+    Yubico = 0x12,
+    /// Timestamp of last master password change
+    LastMasterPasswordChange = 0x13,
+    /// End of header
+    EndOfHeader = 0xff,
+}
+
+impl HeaderFieldType {
+    /// Returns the field type for the given type byte or `None` if the type is unknown.
+    pub fn from_u8(field_type: u8) -> Option<Self> {
+        let res = match field_type {
+            0x00 => HeaderFieldType::Version,
+            0x01 => HeaderFieldType::Uuid,
+            0x02 => HeaderFieldType::Preferences,
+            0x03 => HeaderFieldType::TreeDisplayStatus,
+            0x04 => HeaderFieldType::LastSaveTimestamp,
+            0x05 => HeaderFieldType::LastSaveWho,
+            0x06 => HeaderFieldType::LastSaveWhat,
+            0x07 => HeaderFieldType::LastSaveUser,
+            0x08 => HeaderFieldType::LastSaveHost,
+            0x09 => HeaderFieldType::DatabaseName,
+            0x0a => HeaderFieldType::DatabaseDescription,
+            0x0b => HeaderFieldType::DatabaseFilters,
+            0x0f => HeaderFieldType::RecentlyUsedEntries,
+            0x10 => HeaderFieldType::NamedPasswordPolicies,
+            0x11 => HeaderFieldType::EmptyGroups,
+            0x12 => HeaderFieldType::Yubico,
+            0x13 => HeaderFieldType::LastMasterPasswordChange,
+            0xff => HeaderFieldType::EndOfHeader,
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns the type byte.
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns `true` if the type byte is reserved by the format specification.
+    pub fn is_reserved(field_type: u8) -> bool {
+        HEADER_RESERVED.contains(&field_type)
+    }
+}
+
 /// Password Safe header field.
-#[derive(Debug)]
+///
+/// Integer fields are stored in little-endian byte order, timestamps as 32-bit seconds since the
+/// Unix epoch. UUIDs are kept in the byte order they are stored in.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PwsafeHeaderField {
-    /// Version
+    /// Version, major version in the high byte
     Version(u16),
     /// UUID
     Uuid([u8; 16]),
@@ -96,96 +275,337 @@ pub enum PwsafeHeaderField {
     /// Timestamp of last master password change
     LastMasterPasswordChange(u32),
     /// Unknown field type stored as-is
-    Blob(Vec<u8>),
+    Blob {
+        /// Field type
+        field_type: u8,
+        /// Field contents
+        data: Vec<u8>,
+    },
     /// End of header
     EndOfHeader,
 }
 
 impl PwsafeHeaderField {
     pub fn new(field_type: u8, data: Vec<u8>) -> Result<Self> {
-        let res = match field_type {
-            0x00 => {
+        let res = match HeaderFieldType::from_u8(field_type) {
+            Some(HeaderFieldType::Version) => {
                 let version = parse_u16(data)?;
                 PwsafeHeaderField::Version(version)
             }
-            0x01 => {
+            Some(HeaderFieldType::Uuid) => {
                 if data.len() != 16 {
                     return Err(Error::InvalidLength);
                 }
                 let mut uuid = [0u8; 16];
-                uuid.copy_from_slice(&data.as_slice());
+                uuid.copy_from_slice(data.as_slice());
                 PwsafeHeaderField::Uuid(uuid)
             }
-            0x02 => {
+            Some(HeaderFieldType::Preferences) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::Preferences(s)
             }
-            0x03 => {
+            Some(HeaderFieldType::TreeDisplayStatus) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::TreeDisplayStatus(s)
             }
-            0x04 => {
+            Some(HeaderFieldType::LastSaveTimestamp) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeHeaderField::LastSaveTimestamp(timestamp)
             }
-            0x05 => {
+            Some(HeaderFieldType::LastSaveWho) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::LastSaveWho(s)
             }
-            0x06 => {
+            Some(HeaderFieldType::LastSaveWhat) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::LastSaveWhat(s)
             }
-            0x07 => {
+            Some(HeaderFieldType::LastSaveUser) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::LastSaveUser(s)
             }
-            0x08 => {
+            Some(HeaderFieldType::LastSaveHost) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::LastSaveHost(s)
             }
-            0x09 => {
+            Some(HeaderFieldType::DatabaseName) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::DatabaseName(s)
             }
-            0x0a => {
+            Some(HeaderFieldType::DatabaseDescription) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::DatabaseDescription(s)
             }
-            0x0b => {
+            Some(HeaderFieldType::DatabaseFilters) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::DatabaseFilters(s)
             }
             // 0x0c, 0x0d, 0x0e are reserved
-            0x0f => {
+            Some(HeaderFieldType::RecentlyUsedEntries) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::RecentlyUsedEntries(s)
             }
-            0x10 => {
+            Some(HeaderFieldType::NamedPasswordPolicies) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::NamedPasswordPolicies(s)
             }
-            0x11 => {
+            Some(HeaderFieldType::EmptyGroups) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::EmptyGroups(s)
             }
-            0x12 => {
+            Some(HeaderFieldType::Yubico) => {
                 let s = String::from_utf8(data)?;
                 PwsafeHeaderField::Yubico(s)
             }
-            0x13 => {
+            Some(HeaderFieldType::LastMasterPasswordChange) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeHeaderField::LastMasterPasswordChange(timestamp)
             }
-            0xff => PwsafeHeaderField::EndOfHeader,
-            _ => PwsafeHeaderField::Blob(data),
+            Some(HeaderFieldType::EndOfHeader) => PwsafeHeaderField::EndOfHeader,
+            None => PwsafeHeaderField::Blob { field_type, data },
         };
         Ok(res)
     }
+
+    /// Creates a `RecentlyUsedEntries` field from entry UUIDs, most recent first.
+    ///
+    /// Stored as the number of entries in two hexadecimal digits followed by each UUID in 32
+    /// lowercase hexadecimal digits. At most 255 entries fit.
+    pub fn recently_used_entries(uuids: &[[u8; 16]]) -> Result<Self> {
+        if uuids.len() > 0xff {
+            return Err(Error::InvalidLength);
+        }
+        let mut s = format!("{:02x}", uuids.len());
+        for uuid in uuids {
+            for byte in uuid {
+                s.push_str(&format!("{:02x}", byte));
+            }
+        }
+        Ok(PwsafeHeaderField::RecentlyUsedEntries(s))
+    }
+
+    /// Parses the contents of a `RecentlyUsedEntries` field into entry UUIDs.
+    ///
+    /// See `recently_used_entries` for the format.
+    pub fn parse_recently_used_entries(s: &str) -> Result<Vec<[u8; 16]>> {
+        if s.len() < 2 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidFormat);
+        }
+        let count = u8::from_str_radix(&s[..2], 16).map_err(|_| Error::InvalidFormat)? as usize;
+        if s.len() != 2 + count * 32 {
+            return Err(Error::InvalidLength);
+        }
+        let mut uuids = Vec::with_capacity(count);
+        for chunk in s.as_bytes()[2..].chunks(32) {
+            let mut uuid = [0u8; 16];
+            for (byte, digits) in uuid.iter_mut().zip(chunk.chunks(2)) {
+                let digits = std::str::from_utf8(digits).map_err(|_| Error::InvalidFormat)?;
+                *byte = u8::from_str_radix(digits, 16).map_err(|_| Error::InvalidFormat)?;
+            }
+            uuids.push(uuid);
+        }
+        Ok(uuids)
+    }
+
+    /// Creates a `Uuid` field, returns `InvalidLength` unless `bytes` is 16 bytes long.
+    pub fn uuid(bytes: &[u8]) -> Result<Self> {
+        let uuid = <[u8; 16]>::try_from(bytes).map_err(|_| Error::InvalidLength)?;
+        Ok(PwsafeHeaderField::Uuid(uuid))
+    }
+
+    /// Serializes the field, returns field type and contents for `PwsafeWriter::write_field`.
+    pub fn to_field(&self) -> (u8, Vec<u8>) {
+        let (field_type, data) = match self {
+            PwsafeHeaderField::Version(i) => (HeaderFieldType::Version, i.to_le_bytes().to_vec()),
+            PwsafeHeaderField::Uuid(uuid) => (HeaderFieldType::Uuid, uuid.to_vec()),
+            PwsafeHeaderField::Preferences(s) => {
+                (HeaderFieldType::Preferences, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::TreeDisplayStatus(s) => {
+                (HeaderFieldType::TreeDisplayStatus, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::LastSaveTimestamp(i) => {
+                (HeaderFieldType::LastSaveTimestamp, i.to_le_bytes().to_vec())
+            }
+            PwsafeHeaderField::LastSaveWho(s) => {
+                (HeaderFieldType::LastSaveWho, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::LastSaveWhat(s) => {
+                (HeaderFieldType::LastSaveWhat, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::LastSaveUser(s) => {
+                (HeaderFieldType::LastSaveUser, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::LastSaveHost(s) => {
+                (HeaderFieldType::LastSaveHost, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::DatabaseName(s) => {
+                (HeaderFieldType::DatabaseName, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::DatabaseDescription(s) => {
+                (HeaderFieldType::DatabaseDescription, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::DatabaseFilters(s) => {
+                (HeaderFieldType::DatabaseFilters, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::RecentlyUsedEntries(s) => {
+                (HeaderFieldType::RecentlyUsedEntries, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::NamedPasswordPolicies(s) => (
+                HeaderFieldType::NamedPasswordPolicies,
+                s.as_bytes().to_vec(),
+            ),
+            PwsafeHeaderField::EmptyGroups(s) => {
+                (HeaderFieldType::EmptyGroups, s.as_bytes().to_vec())
+            }
+            PwsafeHeaderField::Yubico(s) => (HeaderFieldType::Yubico, s.as_bytes().to_vec()),
+            PwsafeHeaderField::LastMasterPasswordChange(i) => (
+                HeaderFieldType::LastMasterPasswordChange,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeHeaderField::EndOfHeader => (HeaderFieldType::EndOfHeader, Vec::new()),
+            PwsafeHeaderField::Blob { field_type, data } => return (*field_type, data.clone()),
+        };
+        (field_type.as_u8(), data)
+    }
+}
+
+/// Password Safe record field type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RecordFieldType {
+    /// UUID
+    Uuid = 0x01,
+    /// Group
+    Group = 0x02,
+    /// Title
+    Title = 0x03,
+    /// Username
+    Username = 0x04,
+    /// Notes
+    Notes = 0x05,
+    /// Password
+    Password = 0x06,
+    /// Creation time
+    CreationTime = 0x07,
+    /// Password modification time
+    PasswordModificationTime = 0x08,
+    /// Last access time
+    LastAccessTime = 0x09,
+    /// Password expiry time
+    PasswordExpiryTime = 0x0a,
+    /// Last modification time
+    LastModificationTime = 0x0c,
+    /// URL
+    Url = 0x0d,
+    /// Autotype
+    Autotype = 0x0e,
+    /// Password history
+    PasswordHistory = 0x0f,
+    /// Password policy
+    PasswordPolicy = 0x10,
+    /// Password expiry interval
+    PasswordExpiryInterval = 0x11,
+    /// Run command
+    RunCommand = 0x12,
+    /// Double-click action
+    DoubleClickAction = 0x13,
+    /// Email address
+    EmailAddress = 0x14,
+    /// Protected entry
+    ProtectedEntry = 0x15,
+    /// Own symbols for password
+    OwnSymbolsForPassword = 0x16,
+    /// Shift double-click action
+    ShiftDoubleClickAction = 0x17,
+    /// Password policy name
+    PasswordPolicyName = 0x18,
+    /// Entry keyboard shortcut
+    EntryKeyboardShortcut = 0x19,
+    /// Two-factor key
+    TwoFactorKey = 0x1b,
+    /// Credit card number
+    CreditCardNumber = 0x1c,
+    /// Credit card expiration
+    CreditCardExpiration = 0x1d,
+    /// Credit card verif. value
+    CreditCardVerifValue = 0x1e,
+    /// Credit card PIN
+    CreditCardPin = 0x1f,
+    /// QR code
+    QrCode = 0x20,
+    /// TOTP configuration, introduced with `TwoFactorKey` in format version 0x030d
+    TotpConfig = 0x21,
+    /// TOTP code length, introduced with `TwoFactorKey` in format version 0x030d
+    TotpLength = 0x22,
+    /// TOTP time step, introduced with `TwoFactorKey` in format version 0x030d
+    TotpTimeStep = 0x23,
+    /// TOTP start time, introduced with `TwoFactorKey` in format version 0x030d
+    TotpStartTime = 0x24,
+    /// End of record
+    EndOfRecord = 0xff,
+}
+
+impl RecordFieldType {
+    /// Returns the field type for the given type byte or `None` if the type is unknown.
+    pub fn from_u8(field_type: u8) -> Option<Self> {
+        let res = match field_type {
+            0x01 => RecordFieldType::Uuid,
+            0x02 => RecordFieldType::Group,
+            0x03 => RecordFieldType::Title,
+            0x04 => RecordFieldType::Username,
+            0x05 => RecordFieldType::Notes,
+            0x06 => RecordFieldType::Password,
+            0x07 => RecordFieldType::CreationTime,
+            0x08 => RecordFieldType::PasswordModificationTime,
+            0x09 => RecordFieldType::LastAccessTime,
+            0x0a => RecordFieldType::PasswordExpiryTime,
+            0x0c => RecordFieldType::LastModificationTime,
+            0x0d => RecordFieldType::Url,
+            0x0e => RecordFieldType::Autotype,
+            0x0f => RecordFieldType::PasswordHistory,
+            0x10 => RecordFieldType::PasswordPolicy,
+            0x11 => RecordFieldType::PasswordExpiryInterval,
+            0x12 => RecordFieldType::RunCommand,
+            0x13 => RecordFieldType::DoubleClickAction,
+            0x14 => RecordFieldType::EmailAddress,
+            0x15 => RecordFieldType::ProtectedEntry,
+            0x16 => RecordFieldType::OwnSymbolsForPassword,
+            0x17 => RecordFieldType::ShiftDoubleClickAction,
+            0x18 => RecordFieldType::PasswordPolicyName,
+            0x19 => RecordFieldType::EntryKeyboardShortcut,
+            0x1b => RecordFieldType::TwoFactorKey,
+            0x1c => RecordFieldType::CreditCardNumber,
+            0x1d => RecordFieldType::CreditCardExpiration,
+            0x1e => RecordFieldType::CreditCardVerifValue,
+            0x1f => RecordFieldType::CreditCardPin,
+            0x20 => RecordFieldType::QrCode,
+            0x21 => RecordFieldType::TotpConfig,
+            0x22 => RecordFieldType::TotpLength,
+            0x23 => RecordFieldType::TotpTimeStep,
+            0x24 => RecordFieldType::TotpStartTime,
+            0xff => RecordFieldType::EndOfRecord,
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns the type byte.
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns `true` if the type byte is reserved by the format specification.
+    pub fn is_reserved(field_type: u8) -> bool {
+        RECORD_RESERVED.contains(&field_type)
+    }
 }
 
 /// Password Safe record field.
-#[derive(Debug)]
+///
+/// Integer fields are stored in little-endian byte order, timestamps as 32-bit seconds since the
+/// Unix epoch. UUIDs are kept in the byte order they are stored in, they are not reordered like
+/// Windows GUIDs.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PwsafeRecordField {
     /// UUID
     Uuid([u8; 16]),
@@ -195,7 +615,8 @@ pub enum PwsafeRecordField {
     Title(String),
     /// Username
     Username(String),
-    /// Notes
+    /// Notes, with the `compress` feature notes of 1 KiB or more are stored zlib compressed
+    /// behind a marker Password Safe does not know
     Notes(String),
     /// Password
     Password(String),
@@ -233,7 +654,7 @@ pub enum PwsafeRecordField {
     ShiftDoubleClickAction(u16),
     /// Password policy name
     PasswordPolicyName(String),
-    /// Entry keyboard shortcut
+    /// Entry keyboard shortcut, key code in the low 16 bits and modifier flags above
     EntryKeyboardShortcut(u32),
     /// Two-factor key
     TwoFactorKey(Vec<u8>),
@@ -247,143 +668,338 @@ pub enum PwsafeRecordField {
     CreditCardPin(String),
     /// QR code
     QrCode(String),
+    /// TOTP configuration, the low bits select the algorithm (0 is HMAC-SHA1)
+    TotpConfig(u8),
+    /// Number of digits of a TOTP code
+    TotpLength(u8),
+    /// TOTP time step in seconds
+    TotpTimeStep(u8),
+    /// TOTP start time
+    TotpStartTime(u32),
     /// Unknown field type stored as-is
-    Blob(Vec<u8>),
+    Blob {
+        /// Field type
+        field_type: u8,
+        /// Field contents
+        data: Vec<u8>,
+    },
     /// End of record
     EndOfRecord,
 }
 
 impl PwsafeRecordField {
     pub fn new(field_type: u8, data: Vec<u8>) -> Result<Self> {
-        let res = match field_type {
-            0x01 => {
+        let res = match RecordFieldType::from_u8(field_type) {
+            Some(RecordFieldType::Uuid) => {
                 if data.len() != 16 {
                     return Err(Error::InvalidLength);
                 }
                 let mut uuid = [0u8; 16];
-                uuid.copy_from_slice(&data.as_slice());
+                uuid.copy_from_slice(data.as_slice());
                 PwsafeRecordField::Uuid(uuid)
             }
-            0x02 => {
+            Some(RecordFieldType::Group) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Group(s)
             }
-            0x03 => {
+            Some(RecordFieldType::Title) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Title(s)
             }
-            0x04 => {
+            Some(RecordFieldType::Username) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Username(s)
             }
-            0x05 => {
+            #[cfg(feature = "compress")]
+            Some(RecordFieldType::Notes) if data.starts_with(COMPRESSED_NOTES_MARKER) => {
+                PwsafeRecordField::Notes(decompress_notes(&data[COMPRESSED_NOTES_MARKER.len()..])?)
+            }
+            Some(RecordFieldType::Notes) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Notes(s)
             }
-            0x06 => {
+            Some(RecordFieldType::Password) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Password(s)
             }
-            0x07 => {
+            Some(RecordFieldType::CreationTime) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeRecordField::CreationTime(timestamp)
             }
-            0x08 => {
+            Some(RecordFieldType::PasswordModificationTime) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeRecordField::PasswordModificationTime(timestamp)
             }
-            0x09 => {
+            Some(RecordFieldType::LastAccessTime) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeRecordField::LastAccessTime(timestamp)
             }
-            0x0a => {
+            Some(RecordFieldType::PasswordExpiryTime) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeRecordField::PasswordExpiryTime(timestamp)
             }
             // 0x0b is reserved
-            0x0c => {
+            Some(RecordFieldType::LastModificationTime) => {
                 let timestamp = parse_u32(data)?;
                 PwsafeRecordField::LastModificationTime(timestamp)
             }
-            0x0d => {
+            Some(RecordFieldType::Url) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Url(s)
             }
-            0x0e => {
+            Some(RecordFieldType::Autotype) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::Autotype(s)
             }
-            0x0f => {
+            Some(RecordFieldType::PasswordHistory) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::PasswordHistory(s)
             }
-            0x10 => {
+            Some(RecordFieldType::PasswordPolicy) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::PasswordPolicy(s)
             }
-            0x11 => {
+            Some(RecordFieldType::PasswordExpiryInterval) => {
                 let days = parse_u32(data)?;
                 PwsafeRecordField::PasswordExpiryInterval(days)
             }
-            0x12 => {
+            Some(RecordFieldType::RunCommand) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::RunCommand(s)
             }
-            0x13 => {
+            Some(RecordFieldType::DoubleClickAction) => {
                 let action = parse_u16(data)?;
                 PwsafeRecordField::DoubleClickAction(action)
             }
-            0x14 => {
+            Some(RecordFieldType::EmailAddress) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::EmailAddress(s)
             }
-            0x15 => {
-                if data.len() != 1 {
-                    return Err(Error::InvalidLength);
-                }
-                PwsafeRecordField::ProtectedEntry(data[0])
+            Some(RecordFieldType::ProtectedEntry) => {
+                PwsafeRecordField::ProtectedEntry(parse_u8(data)?)
             }
-            0x16 => {
+            Some(RecordFieldType::OwnSymbolsForPassword) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::OwnSymbolsForPassword(s)
             }
-            0x17 => {
+            Some(RecordFieldType::ShiftDoubleClickAction) => {
                 let action = parse_u16(data)?;
                 PwsafeRecordField::ShiftDoubleClickAction(action)
             }
-            0x18 => {
+            Some(RecordFieldType::PasswordPolicyName) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::PasswordPolicyName(s)
             }
-            0x19 => {
+            Some(RecordFieldType::EntryKeyboardShortcut) => {
                 let shortcut = parse_u32(data)?;
                 PwsafeRecordField::EntryKeyboardShortcut(shortcut)
             }
             // 0x1a is reserved
-            0x1b => PwsafeRecordField::TwoFactorKey(data),
-            0x1c => {
+            Some(RecordFieldType::TwoFactorKey) => PwsafeRecordField::TwoFactorKey(data),
+            Some(RecordFieldType::CreditCardNumber) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::CreditCardNumber(s)
             }
-            0x1d => {
+            Some(RecordFieldType::CreditCardExpiration) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::CreditCardExpiration(s)
             }
-            0x1e => {
+            Some(RecordFieldType::CreditCardVerifValue) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::CreditCardVerifValue(s)
             }
-            0x1f => {
+            Some(RecordFieldType::CreditCardPin) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::CreditCardPin(s)
             }
-            0x20 => {
+            Some(RecordFieldType::QrCode) => {
                 let s = String::from_utf8(data)?;
                 PwsafeRecordField::QrCode(s)
             }
-            0xff => PwsafeRecordField::EndOfRecord,
-            _ => PwsafeRecordField::Blob(data),
+            Some(RecordFieldType::TotpConfig) => PwsafeRecordField::TotpConfig(parse_u8(data)?),
+            Some(RecordFieldType::TotpLength) => PwsafeRecordField::TotpLength(parse_u8(data)?),
+            Some(RecordFieldType::TotpTimeStep) => {
+                PwsafeRecordField::TotpTimeStep(parse_u8(data)?)
+            }
+            Some(RecordFieldType::TotpStartTime) => {
+                let timestamp = parse_u32(data)?;
+                PwsafeRecordField::TotpStartTime(timestamp)
+            }
+            Some(RecordFieldType::EndOfRecord) => PwsafeRecordField::EndOfRecord,
+            None => PwsafeRecordField::Blob { field_type, data },
         };
         Ok(res)
     }
+
+    /// Creates a `TwoFactorKey` field from a base32 encoded TOTP secret.
+    ///
+    /// Case, whitespace and padding are ignored.
+    pub fn two_factor_key_from_base32(s: &str) -> Result<Self> {
+        let key = base32::decode(s).ok_or(Error::InvalidFormat)?;
+        Ok(PwsafeRecordField::TwoFactorKey(key))
+    }
+
+    /// Creates a `Uuid` field, returns `InvalidLength` unless `bytes` is 16 bytes long.
+    pub fn uuid(bytes: &[u8]) -> Result<Self> {
+        let uuid = <[u8; 16]>::try_from(bytes).map_err(|_| Error::InvalidLength)?;
+        Ok(PwsafeRecordField::Uuid(uuid))
+    }
+
+    /// Returns the type byte, like `to_field` without serializing the contents.
+    pub fn field_type(&self) -> u8 {
+        let field_type = match self {
+            PwsafeRecordField::Uuid(_) => RecordFieldType::Uuid,
+            PwsafeRecordField::Group(_) => RecordFieldType::Group,
+            PwsafeRecordField::Title(_) => RecordFieldType::Title,
+            PwsafeRecordField::Username(_) => RecordFieldType::Username,
+            PwsafeRecordField::Notes(_) => RecordFieldType::Notes,
+            PwsafeRecordField::Password(_) => RecordFieldType::Password,
+            PwsafeRecordField::CreationTime(_) => RecordFieldType::CreationTime,
+            PwsafeRecordField::PasswordModificationTime(_) => RecordFieldType::PasswordModificationTime,
+            PwsafeRecordField::LastAccessTime(_) => RecordFieldType::LastAccessTime,
+            PwsafeRecordField::PasswordExpiryTime(_) => RecordFieldType::PasswordExpiryTime,
+            PwsafeRecordField::LastModificationTime(_) => RecordFieldType::LastModificationTime,
+            PwsafeRecordField::Url(_) => RecordFieldType::Url,
+            PwsafeRecordField::Autotype(_) => RecordFieldType::Autotype,
+            PwsafeRecordField::PasswordHistory(_) => RecordFieldType::PasswordHistory,
+            PwsafeRecordField::PasswordPolicy(_) => RecordFieldType::PasswordPolicy,
+            PwsafeRecordField::PasswordExpiryInterval(_) => RecordFieldType::PasswordExpiryInterval,
+            PwsafeRecordField::RunCommand(_) => RecordFieldType::RunCommand,
+            PwsafeRecordField::DoubleClickAction(_) => RecordFieldType::DoubleClickAction,
+            PwsafeRecordField::EmailAddress(_) => RecordFieldType::EmailAddress,
+            PwsafeRecordField::ProtectedEntry(_) => RecordFieldType::ProtectedEntry,
+            PwsafeRecordField::OwnSymbolsForPassword(_) => RecordFieldType::OwnSymbolsForPassword,
+            PwsafeRecordField::ShiftDoubleClickAction(_) => RecordFieldType::ShiftDoubleClickAction,
+            PwsafeRecordField::PasswordPolicyName(_) => RecordFieldType::PasswordPolicyName,
+            PwsafeRecordField::EntryKeyboardShortcut(_) => RecordFieldType::EntryKeyboardShortcut,
+            PwsafeRecordField::TwoFactorKey(_) => RecordFieldType::TwoFactorKey,
+            PwsafeRecordField::CreditCardNumber(_) => RecordFieldType::CreditCardNumber,
+            PwsafeRecordField::CreditCardExpiration(_) => RecordFieldType::CreditCardExpiration,
+            PwsafeRecordField::CreditCardVerifValue(_) => RecordFieldType::CreditCardVerifValue,
+            PwsafeRecordField::CreditCardPin(_) => RecordFieldType::CreditCardPin,
+            PwsafeRecordField::QrCode(_) => RecordFieldType::QrCode,
+            PwsafeRecordField::TotpConfig(_) => RecordFieldType::TotpConfig,
+            PwsafeRecordField::TotpLength(_) => RecordFieldType::TotpLength,
+            PwsafeRecordField::TotpTimeStep(_) => RecordFieldType::TotpTimeStep,
+            PwsafeRecordField::TotpStartTime(_) => RecordFieldType::TotpStartTime,
+            PwsafeRecordField::EndOfRecord => RecordFieldType::EndOfRecord,
+            PwsafeRecordField::Blob { field_type, .. } => return *field_type,
+        };
+        field_type.as_u8()
+    }
+
+    /// Serializes the field, returns field type and contents for `PwsafeWriter::write_field`.
+    pub fn to_field(&self) -> (u8, Vec<u8>) {
+        let (field_type, data) = match self {
+            PwsafeRecordField::Uuid(uuid) => (RecordFieldType::Uuid, uuid.to_vec()),
+            PwsafeRecordField::Group(s) => (RecordFieldType::Group, s.as_bytes().to_vec()),
+            PwsafeRecordField::Title(s) => (RecordFieldType::Title, s.as_bytes().to_vec()),
+            PwsafeRecordField::Username(s) => (RecordFieldType::Username, s.as_bytes().to_vec()),
+            #[cfg(feature = "compress")]
+            PwsafeRecordField::Notes(s)
+                if s.len() >= COMPRESS_NOTES_MIN_LEN || s.as_bytes().starts_with(COMPRESSED_NOTES_MARKER) =>
+            {
+                (RecordFieldType::Notes, compress_notes(s))
+            }
+            PwsafeRecordField::Notes(s) => (RecordFieldType::Notes, s.as_bytes().to_vec()),
+            PwsafeRecordField::Password(s) => (RecordFieldType::Password, s.as_bytes().to_vec()),
+            PwsafeRecordField::CreationTime(i) => {
+                (RecordFieldType::CreationTime, i.to_le_bytes().to_vec())
+            }
+            PwsafeRecordField::PasswordModificationTime(i) => (
+                RecordFieldType::PasswordModificationTime,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::LastAccessTime(i) => {
+                (RecordFieldType::LastAccessTime, i.to_le_bytes().to_vec())
+            }
+            PwsafeRecordField::PasswordExpiryTime(i) => (
+                RecordFieldType::PasswordExpiryTime,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::LastModificationTime(i) => (
+                RecordFieldType::LastModificationTime,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::Url(s) => (RecordFieldType::Url, s.as_bytes().to_vec()),
+            PwsafeRecordField::Autotype(s) => (RecordFieldType::Autotype, s.as_bytes().to_vec()),
+            PwsafeRecordField::PasswordHistory(s) => {
+                (RecordFieldType::PasswordHistory, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::PasswordPolicy(s) => {
+                (RecordFieldType::PasswordPolicy, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::PasswordExpiryInterval(i) => (
+                RecordFieldType::PasswordExpiryInterval,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::RunCommand(s) => {
+                (RecordFieldType::RunCommand, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::DoubleClickAction(i) => {
+                (RecordFieldType::DoubleClickAction, i.to_le_bytes().to_vec())
+            }
+            PwsafeRecordField::EmailAddress(s) => {
+                (RecordFieldType::EmailAddress, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::ProtectedEntry(i) => (RecordFieldType::ProtectedEntry, vec![*i]),
+            PwsafeRecordField::OwnSymbolsForPassword(s) => (
+                RecordFieldType::OwnSymbolsForPassword,
+                s.as_bytes().to_vec(),
+            ),
+            PwsafeRecordField::ShiftDoubleClickAction(i) => (
+                RecordFieldType::ShiftDoubleClickAction,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::PasswordPolicyName(s) => {
+                (RecordFieldType::PasswordPolicyName, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::EntryKeyboardShortcut(i) => (
+                RecordFieldType::EntryKeyboardShortcut,
+                i.to_le_bytes().to_vec(),
+            ),
+            PwsafeRecordField::TwoFactorKey(data) => (RecordFieldType::TwoFactorKey, data.clone()),
+            PwsafeRecordField::CreditCardNumber(s) => {
+                (RecordFieldType::CreditCardNumber, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::CreditCardExpiration(s) => {
+                (RecordFieldType::CreditCardExpiration, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::CreditCardVerifValue(s) => {
+                (RecordFieldType::CreditCardVerifValue, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::CreditCardPin(s) => {
+                (RecordFieldType::CreditCardPin, s.as_bytes().to_vec())
+            }
+            PwsafeRecordField::QrCode(s) => (RecordFieldType::QrCode, s.as_bytes().to_vec()),
+            PwsafeRecordField::TotpConfig(i) => (RecordFieldType::TotpConfig, vec![*i]),
+            PwsafeRecordField::TotpLength(i) => (RecordFieldType::TotpLength, vec![*i]),
+            PwsafeRecordField::TotpTimeStep(i) => (RecordFieldType::TotpTimeStep, vec![*i]),
+            PwsafeRecordField::TotpStartTime(i) => {
+                (RecordFieldType::TotpStartTime, i.to_le_bytes().to_vec())
+            }
+            PwsafeRecordField::EndOfRecord => (RecordFieldType::EndOfRecord, Vec::new()),
+            PwsafeRecordField::Blob { field_type, data } => return (*field_type, data.clone()),
+        };
+        (field_type.as_u8(), data)
+    }
+}
+
+/// Header or record field, for tools treating both alike, see `PwsafeReader::all_fields`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnyField {
+    /// Field of the header, up to and including the end of header marker
+    Header(PwsafeHeaderField),
+    /// Field of a record
+    Record(PwsafeRecordField),
+}
+
+impl AnyField {
+    /// Serializes the field, returns field type and contents for `PwsafeWriter::write_field`.
+    pub fn to_field(&self) -> (u8, Vec<u8>) {
+        match self {
+            AnyField::Header(field) => field.to_field(),
+            AnyField::Record(field) => field.to_field(),
+        }
+    }
 }
@@ -0,0 +1,67 @@
+/// Group hierarchy of a database, see `PwsafeDatabase::groups`.
+///
+/// Each node is a group, the root stands for the top level and has an empty name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupTree {
+    /// Group name, the last element of its path
+    pub name: String,
+    /// Child groups sorted by name
+    pub children: Vec<GroupTree>,
+    /// Indices into `PwsafeDatabase::records` of the records directly in this group
+    pub records: Vec<usize>,
+}
+
+impl GroupTree {
+    /// Splits a group path into group names.
+    ///
+    /// Levels are separated by dots, a dot escaped with a backslash is part of the name. The
+    /// empty path is the top level and has no names.
+    pub fn split_path(path: &str) -> Vec<String> {
+        if path.is_empty() {
+            return Vec::new();
+        }
+        let mut names = Vec::new();
+        let mut name = String::new();
+        let mut chars = path.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'.') => name.push(chars.next().unwrap()),
+                '.' => names.push(std::mem::take(&mut name)),
+                c => name.push(c),
+            }
+        }
+        names.push(name);
+        names
+    }
+
+    /// Returns the group at `path` relative to this one, see `split_path`.
+    pub fn find(&self, path: &str) -> Option<&GroupTree> {
+        let mut node = self;
+        for name in GroupTree::split_path(path) {
+            node = node.children.iter().find(|child| child.name == name)?;
+        }
+        Some(node)
+    }
+
+    /// Returns the group at `path`, creating it and its parents as needed.
+    pub(crate) fn get_or_insert(&mut self, path: &str) -> &mut GroupTree {
+        let mut node = self;
+        for name in GroupTree::split_path(path) {
+            let i = match node.children.binary_search_by(|child| child.name.cmp(&name)) {
+                Ok(i) => i,
+                Err(i) => {
+                    node.children.insert(
+                        i,
+                        GroupTree {
+                            name,
+                            ..Default::default()
+                        },
+                    );
+                    i
+                }
+            };
+            node = &mut node.children[i];
+        }
+        node
+    }
+}
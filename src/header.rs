@@ -0,0 +1,235 @@
+use crate::field::PwsafeHeaderField;
+use std::convert::TryFrom;
+
+/// Type of the header field holding the revision counter, see `PwsafeHeader::set_revision`.
+const REVISION_FIELD_TYPE: u8 = 0xde;
+
+/// Password Safe database header, its fields without the end of header marker.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PwsafeHeader {
+    fields: Vec<PwsafeHeaderField>,
+}
+
+impl PwsafeHeader {
+    /// Creates a header from its fields.
+    pub fn new(fields: Vec<PwsafeHeaderField>) -> Self {
+        PwsafeHeader { fields }
+    }
+
+    /// Returns the fields of the header.
+    pub fn fields(&self) -> &[PwsafeHeaderField] {
+        &self.fields
+    }
+
+    /// Returns the fields of the header for modification.
+    pub fn fields_mut(&mut self) -> &mut Vec<PwsafeHeaderField> {
+        &mut self.fields
+    }
+
+    /// Consumes the header, returning its fields.
+    pub fn into_fields(self) -> Vec<PwsafeHeaderField> {
+        self.fields
+    }
+
+    /// Returns the UUID identifying the database.
+    ///
+    /// It stays the same across saves, so it can be used to match copies of a database.
+    pub fn database_uuid(&self) -> Option<[u8; 16]> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeHeaderField::Uuid(uuid) => Some(*uuid),
+            _ => None,
+        })
+    }
+
+    /// Returns the UUID identifying the database as a `uuid::Uuid`, see `database_uuid`.
+    #[cfg(feature = "uuid")]
+    pub fn database_uuid_typed(&self) -> Option<uuid::Uuid> {
+        self.database_uuid().map(uuid::Uuid::from_bytes)
+    }
+
+    /// Returns the database format version.
+    pub fn version(&self) -> Option<u16> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeHeaderField::Version(version) => Some(*version),
+            _ => None,
+        })
+    }
+
+    /// Returns the raw XML of the saved filters.
+    ///
+    /// The filters are kept verbatim so the ones defined in Password Safe survive a rewrite.
+    /// Parsing their structure is left to the application.
+    pub fn filters_xml(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeHeaderField::DatabaseFilters(xml) => Some(xml.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Checks whether the saved filters are well-formed XML, `None` if there are none.
+    ///
+    /// Only the syntax is checked: matching tags, attributes and a single root element.
+    /// Entity references are not resolved and nothing is validated against the schema of
+    /// Password Safe.
+    #[cfg(feature = "xml")]
+    pub fn filters_xml_well_formed(&self) -> Option<bool> {
+        self.filters_xml().map(xml_well_formed)
+    }
+
+    /// Returns the name and version of the application that saved the database last, e.g.
+    /// `Password Safe V3.65`.
+    ///
+    /// Useful to track down compatibility problems between applications.
+    pub fn last_save_app(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeHeaderField::LastSaveWhat(app) => Some(app.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Replaces the application that saved the database last, `None` removes it.
+    ///
+    /// Applications built on this crate can record themselves, e.g. with `APP_NAME`. Replaces
+    /// the field like `set_filters_xml`.
+    pub fn set_last_save_app(&mut self, app: Option<String>) {
+        self.replace_field(
+            |field| matches!(field, PwsafeHeaderField::LastSaveWhat(_)),
+            app.map(PwsafeHeaderField::LastSaveWhat),
+        );
+    }
+
+    /// Replaces the raw XML of the saved filters, `None` removes them.
+    ///
+    /// An existing filters field keeps its position, otherwise the field is appended.
+    pub fn set_filters_xml(&mut self, xml: Option<String>) {
+        self.replace_field(
+            |field| matches!(field, PwsafeHeaderField::DatabaseFilters(_)),
+            xml.map(PwsafeHeaderField::DatabaseFilters),
+        );
+    }
+
+    /// Returns the revision counter, see `set_revision`.
+    pub fn revision(&self) -> Option<u64> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeHeaderField::Blob { field_type, data } if *field_type == REVISION_FIELD_TYPE => {
+                Some(u64::from_le_bytes(<[u8; 8]>::try_from(&data[..]).ok()?))
+            }
+            _ => None,
+        })
+    }
+
+    /// Replaces the revision counter, `None` removes it.
+    ///
+    /// The counter is a nonstandard extension of this crate for sync tools, a cheap way to tell
+    /// whether a database changed without comparing its contents. It is stored as eight bytes
+    /// in little-endian byte order in a header field of type 0xde, which the specification does
+    /// not define. Password Safe keeps the field when saving but does not increment it, so the
+    /// counter only tracks changes made by applications that do, see `bump_revision`. Off unless
+    /// set.
+    pub fn set_revision(&mut self, revision: Option<u64>) {
+        self.replace_field(
+            |field| matches!(field, PwsafeHeaderField::Blob { field_type, .. } if *field_type == REVISION_FIELD_TYPE),
+            revision.map(revision_field),
+        );
+    }
+
+    /// Increments the revision counter, starting at 1 if there is none, and returns the new
+    /// value. Call before each save, see `set_revision`.
+    pub fn bump_revision(&mut self) -> u64 {
+        let revision = self.revision().map_or(1, |revision| revision.wrapping_add(1));
+        self.set_revision(Some(revision));
+        revision
+    }
+
+    /// Replaces the first field matching `is_match` in place or appends the field, `None`
+    /// removes it.
+    fn replace_field(&mut self, is_match: impl Fn(&PwsafeHeaderField) -> bool, field: Option<PwsafeHeaderField>) {
+        let pos = self.fields.iter().position(is_match);
+        match (pos, field) {
+            (Some(pos), Some(field)) => self.fields[pos] = field,
+            (Some(pos), None) => {
+                self.fields.remove(pos);
+            }
+            (None, Some(field)) => self.fields.push(field),
+            (None, None) => (),
+        }
+    }
+}
+
+/// Returns the header field holding a revision counter, see `PwsafeHeader::set_revision`.
+pub(crate) fn revision_field(revision: u64) -> PwsafeHeaderField {
+    PwsafeHeaderField::Blob {
+        field_type: REVISION_FIELD_TYPE,
+        data: revision.to_le_bytes().to_vec(),
+    }
+}
+
+/// Returns whether `xml` is a well-formed XML document, see
+/// `PwsafeHeader::filters_xml_well_formed`.
+#[cfg(feature = "xml")]
+fn xml_well_formed(xml: &str) -> bool {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut depth: usize = 0;
+    let mut roots = 0;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) | Ok(Event::Empty(element)) if element.attributes().any(|a| a.is_err()) => {
+                return false
+            }
+            Ok(Event::Start(_)) => {
+                if depth == 0 {
+                    roots += 1;
+                }
+                depth += 1;
+            }
+            Ok(Event::Empty(_)) if depth == 0 => roots += 1,
+            Ok(Event::End(_)) => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            // Only whitespace is allowed around the root element
+            Ok(Event::Text(text)) if depth == 0 && !text.as_ref().trim().is_empty() => return false,
+            Ok(Event::Eof) => return depth == 0 && roots == 1,
+            Ok(_) => (),
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Summary of a database header, see `PwsafeReader::metadata`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatabaseMetadata {
+    /// Database format version.
+    pub version: u16,
+    /// Database name.
+    pub name: Option<String>,
+    /// Database description.
+    pub description: Option<String>,
+    /// Time of the last save in seconds since the Unix epoch.
+    pub last_save: Option<u32>,
+}
+
+impl DatabaseMetadata {
+    /// Collects the metadata from header fields.
+    ///
+    /// Returns `None` if there is no version field.
+    pub fn from_header(header: &PwsafeHeader) -> Option<Self> {
+        let mut metadata = DatabaseMetadata {
+            version: header.version()?,
+            ..Default::default()
+        };
+        for field in header.fields() {
+            match field {
+                PwsafeHeaderField::DatabaseName(name) => metadata.name = Some(name.clone()),
+                PwsafeHeaderField::DatabaseDescription(description) => {
+                    metadata.description = Some(description.clone())
+                }
+                PwsafeHeaderField::LastSaveTimestamp(timestamp) => metadata.last_save = Some(*timestamp),
+                _ => (),
+            }
+        }
+        Some(metadata)
+    }
+}
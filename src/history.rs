@@ -0,0 +1,108 @@
+use crate::field::{read_hex, Error, Result};
+use std::convert::TryFrom;
+
+/// Password history of an entry.
+///
+/// Stored in the `PasswordHistory` record field as `fmmnn` followed by the entries: a flag
+/// whether history is kept, the maximum and the actual number of entries as two hexadecimal
+/// digits each. Every entry is the time the password was replaced as eight hexadecimal digits,
+/// the password length in characters as four hexadecimal digits and the password.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PasswordHistory {
+    /// Whether replaced passwords are added to the history
+    pub enabled: bool,
+    /// Maximum number of entries kept
+    pub max_entries: u8,
+    /// Previous passwords, oldest first
+    pub entries: Vec<PasswordHistoryEntry>,
+}
+
+/// Previous password of an entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PasswordHistoryEntry {
+    /// Time the password was replaced in seconds since the Unix epoch
+    pub changed: u32,
+    /// Password
+    pub password: String,
+}
+
+impl PasswordHistory {
+    /// Creates an empty history keeping up to `max_entries` passwords.
+    pub fn new(max_entries: u8) -> Self {
+        PasswordHistory {
+            enabled: true,
+            max_entries,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Parses the contents of a `PasswordHistory` record field.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let enabled = match chars.next() {
+            Some('0') => false,
+            Some('1') => true,
+            _ => return Err(Error::InvalidFormat),
+        };
+        let max_entries = u8::try_from(read_hex(&mut chars, 2)?).map_err(|_| Error::InvalidFormat)?;
+        let count = read_hex(&mut chars, 2)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let changed = read_hex(&mut chars, 8)?;
+            let len = read_hex(&mut chars, 4)? as usize;
+            let password: String = chars.by_ref().take(len).collect();
+            if password.chars().count() != len {
+                return Err(Error::InvalidFormat);
+            }
+            entries.push(PasswordHistoryEntry { changed, password });
+        }
+        if chars.next().is_some() {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(PasswordHistory {
+            enabled,
+            max_entries,
+            entries,
+        })
+    }
+
+    /// Encodes the history as stored in a `PasswordHistory` record field.
+    ///
+    /// Fails if there are more than 255 entries or a password is longer than 65535 characters.
+    pub fn encode(&self) -> Result<String> {
+        if self.entries.len() > 0xff {
+            return Err(Error::InvalidLength);
+        }
+        let mut s = format!(
+            "{}{:02x}{:02x}",
+            self.enabled as u8,
+            self.max_entries,
+            self.entries.len()
+        );
+        for entry in &self.entries {
+            let len = entry.password.chars().count();
+            if len > 0xffff {
+                return Err(Error::InvalidLength);
+            }
+            s.push_str(&format!("{:08x}{:04x}", entry.changed, len));
+            s.push_str(&entry.password);
+        }
+        Ok(s)
+    }
+
+    /// Adds a replaced password as the newest entry, dropping the oldest entries beyond
+    /// `max_entries`.
+    ///
+    /// Does nothing if the history is disabled.
+    pub fn push(&mut self, password: &str, changed: u32) {
+        if !self.enabled {
+            return;
+        }
+        self.entries.push(PasswordHistoryEntry {
+            changed,
+            password: password.to_string(),
+        });
+        let excess = self.entries.len().saturating_sub(self.max_entries as usize);
+        self.entries.drain(..excess);
+    }
+}
@@ -1,18 +1,77 @@
+use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 
-use twofish::cipher::generic_array::typenum::U32;
-use twofish::cipher::generic_array::GenericArray;
+/// Iterations between two calls of the key stretching progress callback.
+const PROGRESS_INTERVAL: u32 = 1 << 16;
 
-/// Returns ECB key generated from password using key stretching algorithm.
-pub fn hash_password(salt: &[u8], iter: u32, password: &[u8]) -> GenericArray<u8, U32> {
+/// Stretches the password into the key protecting a database with the given salt and iteration
+/// count.
+///
+/// The key can be cached and passed to `PwsafeReader::from_key` and `PwsafeWriter::from_key` to
+/// avoid repeating the key stretching.
+pub fn stretch_key(salt: &[u8; 32], iter: u32, password: &[u8]) -> [u8; 32] {
+    stretch_key_with_progress(salt, iter, password, |_| true).expect("stretching is never cancelled")
+}
+
+/// Like `stretch_key`, calling `progress` with the number of iterations done every 65536
+/// iterations.
+///
+/// Returns `None` as soon as `progress` returns `false`, so a UI can cancel stretching with a
+/// high iteration count.
+pub fn stretch_key_with_progress(
+    salt: &[u8; 32],
+    iter: u32,
+    password: &[u8],
+    mut progress: impl FnMut(u32) -> bool,
+) -> Option<[u8; 32]> {
     let mut hasher = Sha256::default();
     hasher.update(password);
-    hasher.update(&salt);
+    hasher.update(salt);
     let mut key = hasher.finalize();
-    for _ in 0..iter {
+    for i in 0..iter {
+        if i % PROGRESS_INTERVAL == 0 && !progress(i) {
+            return None;
+        }
         let mut hasher = Sha256::default();
-        hasher.update(&key);
+        hasher.update(key);
         key = hasher.finalize();
     }
-    key
+    Some(key.into())
+}
+
+/// Returns the hash of a stretched key stored in the preamble to check the password.
+pub(crate) fn key_hash(key: &[u8; 32]) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+/// Returns `true` if both passwords stretch to the same key for the given salt and iteration
+/// count, e.g. to skip rekeying when a password is "changed" to the same value.
+///
+/// Runs the key stretching twice. The keys are compared in constant time.
+pub fn same_key(salt: &[u8; 32], iter: u32, a: &[u8], b: &[u8]) -> bool {
+    let a = stretch_key(salt, iter, a);
+    let b = stretch_key(salt, iter, b);
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Derives the key of record MACs from the HMAC keyed with the database HMAC key L, see
+/// `PwsafeRecord::set_record_mac`.
+pub(crate) fn record_mac_key(hmac: &Hmac<Sha256>) -> [u8; 32] {
+    let mut hmac = hmac.clone();
+    hmac.update(b"pwsafer record MAC key");
+    hmac.finalize().into_bytes().into()
+}
+
+/// Subkeys and initial IV of a database in plaintext.
+///
+/// Only exposed with the `test-vectors` feature to compare against reference test vectors. It
+/// leaks key material, never enable the feature in production builds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyMaterial {
+    /// Field encryption key K
+    pub k: [u8; 32],
+    /// HMAC key L
+    pub l: [u8; 32],
+    /// Initial CBC IV
+    pub iv: [u8; 16],
 }
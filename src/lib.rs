@@ -10,23 +10,87 @@
 //! the whole file. On the other hand, the database must be rekeyed after each modification, so the
 //! whole file must be rewritten from scratch.
 //!
-//! At this time only version 3 database format is supported.
+//! At this time only version 3 database format is supported, `probe` tells the format of a file
+//! without the password.
 //!
-//! High-level interfaces to parse records are not implemented (yet).
+//! On top of the reader, `PwsafeDatabase` reads a whole database into memory as a `PwsafeHeader`
+//! and a list of `PwsafeRecord`s for applications that do not need a representation of their own.
 
 extern crate block_modes;
 extern crate byteorder;
+#[cfg(feature = "flate2")]
+extern crate flate2;
 extern crate hmac;
+#[cfg(feature = "xml")]
+extern crate quick_xml;
 extern crate rand;
 extern crate sha2;
 extern crate twofish;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
+mod base32;
+mod csv;
+mod database;
+mod display;
 mod field;
+mod group;
+mod header;
+mod history;
 mod key;
+mod policy;
+mod probe;
 mod reader;
+mod record;
+mod scan;
+mod wipe;
 mod writer;
 
+pub use self::csv::read_csv;
+pub use self::csv::write_csv;
+pub use self::database::estimate_size;
+pub use self::database::parse_database;
+pub use self::database::PwsafeDatabase;
+pub use self::database::SearchOptions;
+pub use self::field::AnyField;
+pub use self::field::Error as FieldError;
+pub use self::field::HeaderFieldType;
 pub use self::field::PwsafeHeaderField;
 pub use self::field::PwsafeRecordField;
+pub use self::field::RecordFieldType;
+pub use self::group::GroupTree;
+pub use self::header::DatabaseMetadata;
+#[cfg(feature = "test-vectors")]
+pub use self::key::KeyMaterial;
+pub use self::key::same_key;
+pub use self::key::stretch_key;
+pub use self::key::stretch_key_with_progress;
+pub use self::header::PwsafeHeader;
+pub use self::history::PasswordHistory;
+pub use self::history::PasswordHistoryEntry;
+pub use self::policy::NamedPasswordPolicy;
+pub use self::policy::PasswordPolicy;
+pub use self::probe::check_password;
+pub use self::probe::probe;
+pub use self::probe::read_preamble;
+pub use self::probe::FormatInfo;
+pub use self::probe::FormatVersion;
+pub use self::probe::Preamble;
+pub use self::reader::open;
+pub use self::reader::Error as ReaderError;
+pub use self::reader::PreamblePart;
 pub use self::reader::PwsafeReader;
+pub use self::reader::PwsafeReaderBuilder;
+pub use self::record::PwsafeRecord;
+pub use self::record::PwsafeRecordBuilder;
+pub use self::scan::scan;
+pub use self::scan::IntegrityReport;
+pub use self::writer::rekdf;
+pub use self::writer::rotate_many;
 pub use self::writer::PwsafeWriter;
+
+/// Name and version of this crate, e.g. for `PwsafeHeader::set_last_save_app`.
+pub const APP_NAME: &str = concat!("pwsafer ", env!("CARGO_PKG_VERSION"));
+
+/// Unencrypted block following the encrypted fields of a database.
+pub const EOF_SENTINEL: &[u8; 16] = b"PWS3-EOFPWS3-EOF";
@@ -0,0 +1,288 @@
+use crate::field::{read_hex, Error, Result};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use std::convert::TryFrom;
+use std::str::Chars;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "+-=_@#$%^&;:,.<>/~\\[](){}?!|*";
+const EASY_LOWERCASE: &str = "abcdefghijkmnopqrstuvwxyz";
+const EASY_UPPERCASE: &str = "ABCDEFGHJKLMNPQRTUVWXY";
+const EASY_DIGITS: &str = "346789";
+const EASY_SYMBOLS: &str = "+-=_@#$%^&<>/~\\?*";
+const HEX_DIGITS: &str = "0123456789abcdef";
+const VOWELS: &str = "aeiou";
+const CONSONANTS: &str = "bcdfghjkmnprstvwxz";
+
+/// Password policy of an entry or a named policy.
+///
+/// Stored in the `PasswordPolicy` record field as `ffffnnnllluuudddsss`: flags followed by the
+/// password length and the minimum number of lowercase letters, uppercase letters, digits and
+/// symbols, all in hexadecimal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Policy flags, see the associated constants.
+    pub flags: u16,
+    /// Password length
+    pub length: u16,
+    /// Minimum number of lowercase letters
+    pub min_lowercase: u16,
+    /// Minimum number of uppercase letters
+    pub min_uppercase: u16,
+    /// Minimum number of digits
+    pub min_digits: u16,
+    /// Minimum number of symbols
+    pub min_symbols: u16,
+    /// Symbols to use, empty for the default set
+    pub symbols: String,
+}
+
+impl PasswordPolicy {
+    /// Use lowercase letters.
+    pub const USE_LOWERCASE: u16 = 0x8000;
+    /// Use uppercase letters.
+    pub const USE_UPPERCASE: u16 = 0x4000;
+    /// Use digits.
+    pub const USE_DIGITS: u16 = 0x2000;
+    /// Use symbols.
+    pub const USE_SYMBOLS: u16 = 0x1000;
+    /// Use hexadecimal digits only.
+    pub const USE_HEX_DIGITS: u16 = 0x0800;
+    /// Avoid characters that are easily confused.
+    pub const USE_EASY_VISION: u16 = 0x0400;
+    /// Make the password pronounceable.
+    pub const MAKE_PRONOUNCEABLE: u16 = 0x0200;
+
+    /// Parses the contents of a `PasswordPolicy` record field.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let policy = PasswordPolicy::read(&mut chars)?;
+        if chars.next().is_some() {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(policy)
+    }
+
+    /// Encodes the policy as stored in a `PasswordPolicy` record field.
+    ///
+    /// Symbols are not part of the encoding, entries store them in `OwnSymbolsForPassword`.
+    pub fn encode(&self) -> String {
+        format!(
+            "{:04x}{:03x}{:03x}{:03x}{:03x}{:03x}",
+            self.flags,
+            self.length,
+            self.min_lowercase,
+            self.min_uppercase,
+            self.min_digits,
+            self.min_symbols
+        )
+    }
+
+    /// Returns `true` if the given flag is set.
+    pub fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Generates a password following the policy.
+    ///
+    /// The password has the policy length, or the sum of the minimums if that is larger, and
+    /// contains at least the minimum number of characters of each enabled class. Characters are
+    /// taken from `symbols` if set, the default symbols otherwise. Easy vision avoids characters
+    /// that are easily confused, hexadecimal digits only uses `0-9a-f` and ignores the other
+    /// classes. Pronounceable passwords alternate consonants and vowels, with the required
+    /// digits, symbols and uppercase letters mixed in. A policy without any class enabled uses
+    /// letters and digits.
+    pub fn generate(&self, rng: &mut impl RngCore) -> String {
+        if self.has_flag(PasswordPolicy::USE_HEX_DIGITS) {
+            return (0..self.length).map(|_| pick(rng, HEX_DIGITS)).collect();
+        }
+
+        let easy = self.has_flag(PasswordPolicy::USE_EASY_VISION);
+        let symbols = match (self.symbols.is_empty(), easy) {
+            (false, _) => self.symbols.as_str(),
+            (true, false) => SYMBOLS,
+            (true, true) => EASY_SYMBOLS,
+        };
+        let mut classes = vec![
+            (
+                PasswordPolicy::USE_LOWERCASE,
+                self.min_lowercase,
+                if easy { EASY_LOWERCASE } else { LOWERCASE },
+            ),
+            (
+                PasswordPolicy::USE_UPPERCASE,
+                self.min_uppercase,
+                if easy { EASY_UPPERCASE } else { UPPERCASE },
+            ),
+            (
+                PasswordPolicy::USE_DIGITS,
+                self.min_digits,
+                if easy { EASY_DIGITS } else { DIGITS },
+            ),
+            (PasswordPolicy::USE_SYMBOLS, self.min_symbols, symbols),
+        ];
+        classes.retain(|&(flag, _, chars)| self.has_flag(flag) && !chars.is_empty());
+        if classes.is_empty() {
+            classes = vec![(0, 0, LOWERCASE), (0, 0, UPPERCASE), (0, 0, DIGITS)];
+        }
+        let min_total: usize = classes.iter().map(|&(_, min, _)| min as usize).sum();
+        let length = (self.length as usize).max(min_total);
+
+        let letters = classes.iter().any(|&(flag, _, _)| {
+            flag == PasswordPolicy::USE_LOWERCASE || flag == PasswordPolicy::USE_UPPERCASE
+        });
+        if self.has_flag(PasswordPolicy::MAKE_PRONOUNCEABLE) && letters {
+            return self.generate_pronounceable(rng, length, &classes);
+        }
+
+        let mut password = Vec::with_capacity(length);
+        for &(_, min, chars) in &classes {
+            password.extend((0..min).map(|_| pick(rng, chars)));
+        }
+        let all: String = classes.iter().map(|&(_, _, chars)| chars).collect();
+        while password.len() < length {
+            password.push(pick(rng, &all));
+        }
+        password.shuffle(rng);
+        password.into_iter().collect()
+    }
+
+    /// Generates alternating consonants and vowels, then places the required characters of the
+    /// other classes at random positions.
+    fn generate_pronounceable(
+        &self,
+        rng: &mut impl RngCore,
+        length: usize,
+        classes: &[(u16, u16, &str)],
+    ) -> String {
+        let vowel_first = rng.gen::<bool>();
+        let mut password: Vec<char> = (0..length)
+            .map(|i| {
+                if (i % 2 == 0) == vowel_first {
+                    pick(rng, VOWELS)
+                } else {
+                    pick(rng, CONSONANTS)
+                }
+            })
+            .collect();
+
+        let mut positions: Vec<usize> = (0..length).collect();
+        positions.shuffle(rng);
+        let mut positions = positions.into_iter();
+        let lowercase = classes
+            .iter()
+            .any(|&(flag, _, _)| flag == PasswordPolicy::USE_LOWERCASE);
+        for &(flag, min, chars) in classes {
+            match flag {
+                PasswordPolicy::USE_LOWERCASE => (),
+                PasswordPolicy::USE_UPPERCASE if lowercase => {
+                    for i in positions.by_ref().take(min as usize) {
+                        password[i] = password[i].to_ascii_uppercase();
+                    }
+                }
+                PasswordPolicy::USE_UPPERCASE => {
+                    password
+                        .iter_mut()
+                        .for_each(|c| *c = c.to_ascii_uppercase());
+                }
+                _ => {
+                    for i in positions.by_ref().take(min as usize) {
+                        password[i] = pick(rng, chars);
+                    }
+                }
+            }
+        }
+        password.into_iter().collect()
+    }
+
+    fn read(chars: &mut Chars) -> Result<Self> {
+        let mut read_u16 = |len| u16::try_from(read_hex(chars, len)?).map_err(|_| Error::InvalidFormat);
+        Ok(PasswordPolicy {
+            flags: read_u16(4)?,
+            length: read_u16(3)?,
+            min_lowercase: read_u16(3)?,
+            min_uppercase: read_u16(3)?,
+            min_digits: read_u16(3)?,
+            min_symbols: read_u16(3)?,
+            symbols: String::new(),
+        })
+    }
+}
+
+/// Password policy stored by name in the `NamedPasswordPolicies` header field.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamedPasswordPolicy {
+    /// Policy name
+    pub name: String,
+    /// Policy
+    pub policy: PasswordPolicy,
+}
+
+impl NamedPasswordPolicy {
+    /// Parses the contents of a `NamedPasswordPolicies` header field.
+    ///
+    /// The field holds the number of policies as two hexadecimal digits followed by each
+    /// policy: name length (two hexadecimal digits, in characters), name, the policy encoded as
+    /// in the `PasswordPolicy` record field, symbols length and symbols.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        let mut chars = s.chars();
+        let count = read_hex(&mut chars, 2)?;
+        let mut policies = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name = read_string(&mut chars)?;
+            let mut policy = PasswordPolicy::read(&mut chars)?;
+            policy.symbols = read_string(&mut chars)?;
+            policies.push(NamedPasswordPolicy { name, policy });
+        }
+        if chars.next().is_some() {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(policies)
+    }
+
+    /// Encodes policies as stored in a `NamedPasswordPolicies` header field.
+    ///
+    /// Fails if there are more than 255 policies or a name or symbol set is longer than 255
+    /// characters.
+    pub fn encode_list(policies: &[NamedPasswordPolicy]) -> Result<String> {
+        if policies.len() > 0xff {
+            return Err(Error::InvalidLength);
+        }
+        let mut s = format!("{:02x}", policies.len());
+        for p in policies {
+            write_string(&mut s, &p.name)?;
+            s.push_str(&p.policy.encode());
+            write_string(&mut s, &p.policy.symbols)?;
+        }
+        Ok(s)
+    }
+}
+
+/// Returns a random character of the given set.
+fn pick(rng: &mut impl RngCore, chars: &str) -> char {
+    let chars: Vec<char> = chars.chars().collect();
+    chars[rng.gen_range(0..chars.len())]
+}
+
+/// Reads a string prefixed with its length in characters as two hexadecimal digits.
+fn read_string(chars: &mut Chars) -> Result<String> {
+    let len = read_hex(chars, 2)? as usize;
+    let s: String = chars.take(len).collect();
+    if s.chars().count() != len {
+        return Err(Error::InvalidFormat);
+    }
+    Ok(s)
+}
+
+/// Appends a string prefixed with its length in characters as two hexadecimal digits.
+fn write_string(out: &mut String, s: &str) -> Result<()> {
+    let len = s.chars().count();
+    if len > 0xff {
+        return Err(Error::InvalidLength);
+    }
+    out.push_str(&format!("{:02x}", len));
+    out.push_str(s);
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::reader::{check_gzip, Error, PreamblePart, PwsafeReaderBuilder, Result};
+use std::io::{self, Read};
+use std::ops::RangeInclusive;
+
+/// Database file format, see `probe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatVersion {
+    /// Password Safe v3, tag `PWS3`
+    V3,
+    /// Password Safe v4, tag `PWS4`, not supported by `PwsafeReader`
+    V4,
+}
+
+/// Outcome of `probe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatInfo {
+    /// File format
+    pub version: FormatVersion,
+    /// Key stretching iteration count, only known for v3
+    pub iterations: Option<u32>,
+}
+
+/// Detects the file format from the unencrypted start of a database without the password.
+///
+/// Reads the tag and, for v3, the salt and iteration count. Fails with `InvalidTag` if the file
+/// is not a Password Safe database, with `GzipCompressed` for a compressed one and with
+/// `HeaderTooShort` if a v3 file ends before the iteration count.
+///
+/// ```rust
+/// use pwsafer::FormatVersion;
+/// use std::fs::File;
+///
+/// let file = File::open("tests/pwsafe.psafe3").unwrap();
+/// let info = pwsafer::probe(file).unwrap();
+/// assert_eq!(info.version, FormatVersion::V3);
+/// assert_eq!(info.iterations, Some(2048));
+/// ```
+pub fn probe<R: Read>(mut inner: R) -> Result<FormatInfo> {
+    let tag = read_tag(&mut inner)?;
+    let version = match &tag {
+        b"PWS3" => FormatVersion::V3,
+        b"PWS4" => {
+            return Ok(FormatInfo {
+                version: FormatVersion::V4,
+                iterations: None,
+            })
+        }
+        _ => return Err(check_gzip(&tag)),
+    };
+    let mut preamble = Preamble {
+        tag,
+        ..Preamble::default()
+    };
+    preamble.read_parts(&mut inner, PreamblePart::Salt..=PreamblePart::Iter)?;
+    Ok(FormatInfo {
+        version,
+        iterations: Some(preamble.iter),
+    })
+}
+
+/// Checks whether `password` opens the database, reading only the unencrypted preamble up to the
+/// password hash.
+///
+/// Returns `Ok(false)` for a wrong password. Malformed files fail like `PwsafeReader::new`,
+/// e.g. with `InvalidTag` or `HeaderTooShort`. Nothing is decrypted, so a correct password says
+/// nothing about the integrity of the rest of the file. Key stretching takes as long as opening
+/// the database, it is the decryption of the fields that is skipped. The iteration count is not
+/// limited, use `PwsafeReaderBuilder::check_password` with `max_iterations` for untrusted
+/// files.
+///
+/// ```rust
+/// use std::fs::File;
+///
+/// assert!(pwsafer::check_password(File::open("tests/pwsafe.psafe3").unwrap(), b"password").unwrap());
+/// assert!(!pwsafer::check_password(File::open("tests/pwsafe.psafe3").unwrap(), b"wrong").unwrap());
+/// ```
+pub fn check_password<R: Read>(inner: R, password: &[u8]) -> Result<bool> {
+    PwsafeReaderBuilder::new().check_password(inner, password)
+}
+
+/// Unencrypted start of a v3 database, see `read_preamble`.
+///
+/// The keys are stored encrypted with the stretched password, so none of this reveals the
+/// contents. A new salt and new keys are generated on every save.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Preamble {
+    /// Tag, `PWS3`
+    pub tag: [u8; 4],
+    /// Salt for key stretching
+    pub salt: [u8; 32],
+    /// Key stretching iteration count
+    pub iter: u32,
+    /// SHA-256 of the stretched password
+    pub password_hash: [u8; 32],
+    /// Field encryption key K, encrypted
+    pub k: [u8; 32],
+    /// HMAC key L, encrypted
+    pub l: [u8; 32],
+    /// Initial CBC IV
+    pub iv: [u8; 16],
+}
+
+impl Preamble {
+    /// Reads the given parts in file order, reporting a premature end as `HeaderTooShort`.
+    pub(crate) fn read_parts<R: Read>(&mut self, inner: &mut R, parts: RangeInclusive<PreamblePart>) -> Result<()> {
+        for part in PreamblePart::ALL.iter().copied().filter(|part| parts.contains(part)) {
+            let mut iter = [0; 4];
+            let buf: &mut [u8] = match part {
+                PreamblePart::Tag => &mut self.tag,
+                PreamblePart::Salt => &mut self.salt,
+                PreamblePart::Iter => &mut iter,
+                PreamblePart::TrueHash => &mut self.password_hash,
+                PreamblePart::K => &mut self.k,
+                PreamblePart::L => &mut self.l,
+                PreamblePart::Iv => &mut self.iv,
+            };
+            read_preamble_part(inner, buf, part)?;
+            if part == PreamblePart::Iter {
+                self.iter = u32::from_le_bytes(iter);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the preamble as stored in the file.
+    pub fn to_bytes(&self) -> [u8; 152] {
+        let mut bytes = [0; 152];
+        let parts: [&[u8]; 7] = [
+            &self.tag,
+            &self.salt,
+            &self.iter.to_le_bytes(),
+            &self.password_hash,
+            &self.k,
+            &self.l,
+            &self.iv,
+        ];
+        let mut pos = 0;
+        for part in &parts {
+            bytes[pos..pos + part.len()].copy_from_slice(part);
+            pos += part.len();
+        }
+        bytes
+    }
+}
+
+/// Reads the unencrypted preamble of a database without the password.
+///
+/// Backup tools can compare it between a database and its copy to detect a changed salt or
+/// keys, i.e. a new save, or corruption of the preamble. Fails like `probe`, `HeaderTooShort`
+/// names the missing part.
+///
+/// ```rust
+/// use std::fs;
+///
+/// let data = fs::read("tests/pwsafe.psafe3").unwrap();
+/// let preamble = pwsafer::read_preamble(&data[..]).unwrap();
+/// assert_eq!(preamble.iter, 2048);
+/// assert_eq!(&preamble.to_bytes()[..], &data[..152]);
+/// ```
+pub fn read_preamble<R: Read>(mut inner: R) -> Result<Preamble> {
+    read_preamble_until(&mut inner, PreamblePart::Iv)
+}
+
+/// Reads the preamble of a v3 database up to and including `last`, the parts after it are left
+/// zeroed.
+///
+/// Fails like `read_preamble`.
+pub(crate) fn read_preamble_until<R: Read>(inner: &mut R, last: PreamblePart) -> Result<Preamble> {
+    let tag = read_tag(inner)?;
+    if &tag != b"PWS3" {
+        return Err(check_gzip(&tag));
+    }
+    let mut preamble = Preamble {
+        tag,
+        ..Preamble::default()
+    };
+    preamble.read_parts(inner, PreamblePart::Salt..=last)?;
+    Ok(preamble)
+}
+
+/// Reads the tag of a database, a file too short to hold it is no database.
+fn read_tag<R: Read>(inner: &mut R) -> Result<[u8; 4]> {
+    let mut tag = [0; 4];
+    inner.read_exact(&mut tag).map_err(|_| Error::InvalidTag)?;
+    Ok(tag)
+}
+
+/// Reads one part of the unencrypted preamble, reporting a premature end as `HeaderTooShort`.
+fn read_preamble_part<R: Read>(inner: &mut R, buf: &mut [u8], part: PreamblePart) -> Result<()> {
+    inner.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::HeaderTooShort(part),
+        _ => Error::IoError(e),
+    })
+}
@@ -2,44 +2,179 @@ use block_modes::block_padding::{ZeroPadding};
 use block_modes::{BlockMode, Cbc, Ecb};
 use block_modes::cipher::NewBlockCipher;
 use byteorder::{LittleEndian, ReadBytesExt};
-use field::PwsafeHeaderField;
+use crate::field::{self, AnyField, HeaderFieldType, PwsafeHeaderField, PwsafeRecordField, RecordFieldType};
+use crate::header::{DatabaseMetadata, PwsafeHeader};
 use hmac::{crypto_mac, Hmac, Mac, NewMac};
-use key::hash_password;
-use sha2::{Digest, Sha256};
-use std::cmp::min;
+use crate::key::{key_hash, record_mac_key, stretch_key, stretch_key_with_progress};
+#[cfg(feature = "test-vectors")]
+use crate::key::KeyMaterial;
+use crate::probe::{read_preamble_until, Preamble};
+use crate::EOF_SENTINEL;
+use crate::record::PwsafeRecord;
+use crate::wipe::zeroize;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::error;
 use std::fmt;
+use std::iter;
+use std::ops::Range;
+use std::sync::atomic::{self, AtomicBool};
 use std::io::{self, Cursor, Read};
-use twofish::{Twofish, cipher::generic_array::GenericArray};
+use twofish::Twofish;
+
+#[cfg(feature = "flate2")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncRead;
 
 /// A specialized `Result` type for Password Safe database reader.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 /// Password Safe database reader error.
 pub enum Error {
     /// Incorrect file signature, file is not a password safe database.
     InvalidTag,
+    /// The file is gzip compressed, e.g. a `.psafe3.gz` backup. Decompress it before reading,
+    /// e.g. with `PwsafeReader::new_gz`.
+    GzipCompressed,
     /// Invalid password.
     InvalidPassword,
+    /// The password is correct, but the first field does not decrypt to a plausible length.
+    ///
+    /// The encryption and HMAC keys were probably wrapped in a way this crate does not expect,
+    /// e.g. by another implementation with a different ECB setup. Corrupted data right after the
+    /// preamble has the same effect.
+    KeyUnwrapFailed,
     /// Invalid header (mandatory version field is missing or has wrong length).
     InvalidHeader,
+    /// The file ends before the given part of the preamble.
+    HeaderTooShort(PreamblePart),
     /// Invalid key for block cipher
     InvalidCipherKey,
+    /// EOF block is missing, the database is truncated or corrupted.
+    MissingEof,
+    /// Field length exceeds the remaining data.
+    InvalidFieldLength,
+    /// Record is not terminated by an end of record field.
+    InvalidRecord,
+    /// Method called after fields were already read, see `read_version`.
+    OutOfOrder,
+    /// Fewer than 32 bytes of HMAC follow the EOF block, the database is truncated.
+    InvalidMacLength,
+    /// Field of a reserved type, see `PwsafeReaderBuilder::reject_reserved`.
+    ReservedFieldType(u8),
+    /// Field of a type the specification does not define, see `PwsafeReaderBuilder::strict`.
+    UnknownFieldType(u8),
+    /// Iteration count above the limit, see `PwsafeReaderBuilder::max_iterations`.
+    ExcessiveIterations(u32),
+    /// Key stretching was cancelled by the progress callback.
+    Cancelled,
     /// An I/O error.
     IoError(io::Error),
     /// HMAC error.
     MacError(crypto_mac::MacError),
+    /// Field parsing error.
+    FieldError(field::Error),
+    /// Parsing error of a header field read by `read_header` or `typed_header_fields`.
+    HeaderFieldError(HeaderFieldType, field::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::InvalidTag => write!(f, "Not a Password Safe database file"),
+            Error::GzipCompressed => write!(f, "File is gzip compressed, decompress it first"),
             Error::InvalidPassword => write!(f, "Invalid password"),
+            Error::KeyUnwrapFailed => write!(f, "Password is correct but the database keys do not decrypt the data"),
             Error::InvalidHeader => write!(f, "Invalid header"),
+            Error::HeaderTooShort(part) => write!(f, "File too short, {} is missing", part),
             Error::InvalidCipherKey => write!(f, "Invalid block cipher key"),
+            Error::MissingEof => write!(f, "EOF block is missing"),
+            Error::InvalidFieldLength => write!(f, "Invalid field length"),
+            Error::InvalidRecord => write!(f, "Record is not terminated"),
+            Error::OutOfOrder => write!(f, "Fields were already read"),
+            Error::InvalidMacLength => write!(f, "HMAC is truncated"),
+            Error::ReservedFieldType(t) => write!(f, "Reserved field type 0x{:02x}", t),
+            Error::UnknownFieldType(t) => write!(f, "Unknown field type 0x{:02x}", t),
+            Error::ExcessiveIterations(iter) => write!(f, "Too many iterations ({})", iter),
+            Error::Cancelled => write!(f, "Cancelled"),
             Error::IoError(ref e) => e.fmt(f),
             Error::MacError(ref e) => e.fmt(f),
+            Error::FieldError(ref e) => e.fmt(f),
+            Error::HeaderFieldError(field_type, ref e) => write!(f, "Failed to parse {:?}: {}", field_type, e),
+        }
+    }
+}
+
+/// Part of the unencrypted preamble, see `Error::HeaderTooShort`.
+///
+/// Parts are ordered as they are stored in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PreamblePart {
+    /// Tag, `PWS3`
+    Tag,
+    /// Salt for key stretching
+    Salt,
+    /// Key stretching iteration count
+    Iter,
+    /// SHA-256 of the stretched password
+    TrueHash,
+    /// Encrypted field encryption key K
+    K,
+    /// Encrypted HMAC key L
+    L,
+    /// Initial CBC IV
+    Iv,
+}
+
+impl PreamblePart {
+    /// All parts in file order.
+    pub(crate) const ALL: [PreamblePart; 7] = [
+        PreamblePart::Tag,
+        PreamblePart::Salt,
+        PreamblePart::Iter,
+        PreamblePart::TrueHash,
+        PreamblePart::K,
+        PreamblePart::L,
+        PreamblePart::Iv,
+    ];
+}
+
+impl fmt::Display for PreamblePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreamblePart::Tag => write!(f, "tag"),
+            PreamblePart::Salt => write!(f, "salt"),
+            PreamblePart::Iter => write!(f, "iteration count"),
+            PreamblePart::TrueHash => write!(f, "password hash"),
+            PreamblePart::K => write!(f, "encryption key"),
+            PreamblePart::L => write!(f, "HMAC key"),
+            PreamblePart::Iv => write!(f, "IV"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::IoError(ref e) => Some(e),
+            Error::FieldError(ref e) => Some(e),
+            Error::HeaderFieldError(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Allows `?` on reader results in code returning writer results, e.g. when copying a database.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::IoError(e) => e,
+            Error::FieldError(e) => e.into(),
+            Error::HeaderFieldError(_, field::Error::IoError(e)) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
         }
     }
 }
@@ -56,13 +191,28 @@ impl From<crypto_mac::MacError> for Error {
     }
 }
 
+impl From<field::Error> for Error {
+    fn from(err: field::Error) -> Error {
+        Error::FieldError(err)
+    }
+}
+
 type TwofishCbc = Cbc<Twofish, ZeroPadding>;
+
+/// Length of the unencrypted preamble: tag, salt, iteration count, password hash, keys and IV.
+const PREAMBLE_LEN: u64 = 4 + 32 + 4 + 32 + 32 + 32 + 16;
+
+/// First bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Largest `PwsafeReaderBuilder::size_hint` allocated up front.
+const MAX_SIZE_HINT: u64 = 1 << 30;
 type HmacSha256 = Hmac<Sha256>;
 
 /// Password safe reader.
 ///
 /// ```rust
-/// use pwsafe::PwsafeReader;
+/// use pwsafer::PwsafeReader;
 /// use std::fs::File;
 /// use std::io::BufReader;
 ///
@@ -76,75 +226,442 @@ type HmacSha256 = Hmac<Sha256>;
 /// }
 /// db.verify().unwrap();
 /// ```
+///
+/// The reader is `Send` and `Sync` whenever the underlying reader is, so it can be opened on one
+/// thread and handed to another after the slow key stretching.
 pub struct PwsafeReader<R> {
     _inner: R,
     buffer: Cursor<Vec<u8>>,
+    mac: Vec<u8>,
     hmac: HmacSha256,
+    /// HMAC keyed with the database HMAC key before any field was read
+    initial_hmac: HmacSha256,
+    /// Salt for key stretching
+    salt: [u8; 32],
     /// Number of iterations
     iter: u32,
+    /// Whether the end of header field was not read yet
+    in_header: bool,
+    /// Options the database was opened with
+    options: PwsafeReaderBuilder,
+    /// Whether recovery mode skipped data, the HMAC would not cover it
+    skipped: bool,
+    /// Number of fields read, see `fields_read`
+    fields_read: usize,
+    #[cfg(feature = "debug-hmac")]
+    hmac_snapshots: Vec<[u8; 32]>,
+    #[cfg(feature = "test-vectors")]
+    keys: KeyMaterial,
 }
 
-impl<R: Read> PwsafeReader<R> {
+/// Builder for a `PwsafeReader` with non-default options.
+///
+/// ```rust
+/// use pwsafer::PwsafeReaderBuilder;
+/// use std::fs::File;
+///
+/// let file = File::open("tests/pwsafe.psafe3").unwrap();
+/// let mut db = PwsafeReaderBuilder::new()
+///     .eof_sentinel(*b"PWS3-EOFPWS3-EOF")
+///     .open(file, b"password")
+///     .unwrap();
+/// db.verify_all().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct PwsafeReaderBuilder {
+    eof_sentinel: [u8; 16],
+    reject_reserved: bool,
+    strict: bool,
+    recovery: bool,
+    clear_on_eof: bool,
+    max_iterations: u32,
+    size_hint: Option<u64>,
+}
+
+impl Default for PwsafeReaderBuilder {
+    fn default() -> Self {
+        PwsafeReaderBuilder {
+            eof_sentinel: *EOF_SENTINEL,
+            reject_reserved: false,
+            strict: false,
+            recovery: false,
+            clear_on_eof: false,
+            max_iterations: u32::MAX,
+            size_hint: None,
+        }
+    }
+}
+
+impl PwsafeReaderBuilder {
+    /// Creates a builder with default options.
+    pub fn new() -> Self {
+        PwsafeReaderBuilder::default()
+    }
+
+    /// Sets the unencrypted block expected after the encrypted fields.
+    ///
+    /// Defaults to `EOF_SENTINEL`. Other values are only useful to read nonstandard databases.
+    pub fn eof_sentinel(mut self, sentinel: [u8; 16]) -> Self {
+        self.eof_sentinel = sentinel;
+        self
+    }
+
+    /// Makes `read_field` fail with `ReservedFieldType` on field types the specification
+    /// reserves, to check whether a database strictly conforms to it.
+    ///
+    /// Disabled by default, reserved fields are then parsed as `Blob`s like unknown ones.
+    pub fn reject_reserved(mut self, reject: bool) -> Self {
+        self.reject_reserved = reject;
+        self
+    }
+
+    /// Makes `read_field` fail with `UnknownFieldType` on field types the specification does
+    /// not define, which would otherwise be parsed as `Blob`s.
+    ///
+    /// Disabled by default. Meant for conformance testing, databases of newer Password Safe
+    /// versions with fields this crate does not know yet fail in strict mode.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Enables recovery mode to salvage what is left of a corrupted database.
+    ///
+    /// A missing EOF block is tolerated by treating all remaining data as fields, and
+    /// `read_field` returns `None` instead of failing on a field running past the end of the
+    /// data. `verify` still reports a mismatching or missing HMAC, take that as a warning that
+    /// the fields read may be corrupted, truncated or tampered with. Never use this mode for
+    /// anything but data recovery.
+    pub fn recovery(mut self, recovery: bool) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Makes `read_field` call `PwsafeReader::clear_buffer` once it reaches the EOF block.
+    ///
+    /// Disabled by default. Fields can not be read again after the buffer was cleared.
+    pub fn clear_on_eof(mut self, clear: bool) -> Self {
+        self.clear_on_eof = clear;
+        self
+    }
+
+    /// Makes opening with a password fail with `ExcessiveIterations` if the database declares
+    /// more than `max` key stretching iterations.
+    ///
+    /// Stretching runs before the password can be checked, so a crafted file with an iteration
+    /// count close to `u32::MAX` keeps the process busy for a very long time. Set a limit when
+    /// opening untrusted files. Unlimited by default.
+    pub fn max_iterations(mut self, max: u32) -> Self {
+        self.max_iterations = max;
+        self
+    }
+
+    /// Sets the expected length of the whole database file, e.g. from its metadata.
+    ///
+    /// The buffer holding the encrypted fields is allocated at its final size up front instead
+    /// of growing while reading. A wrong hint costs at most a reallocation, hints above 1 GiB
+    /// are capped.
+    pub fn size_hint(mut self, len: u64) -> Self {
+        self.size_hint = Some(len);
+        self
+    }
+
     /// Creates a new `PwsafeReader` with the given password and reads ps3db data into buffer.
-    pub fn new(mut inner: R, password: &[u8]) -> Result<Self> {
-        let mut tag = [0; 4];
-        if inner.read_exact(&mut tag).is_err() {
-            return Err(Error::InvalidTag);
-        };
+    pub fn open<R: Read>(&self, inner: R, password: &[u8]) -> Result<PwsafeReader<R>> {
+        self.open_with_progress(inner, password, |_, _| true)
+    }
+
+    /// Like `open`, calling `progress` with the iterations done and the total iteration count
+    /// while stretching the key.
+    ///
+    /// Fails with `Cancelled` as soon as `progress` returns `false`, see
+    /// `stretch_key_with_progress`.
+    pub fn open_with_progress<R: Read>(
+        &self,
+        inner: R,
+        password: &[u8],
+        mut progress: impl FnMut(u32, u32) -> bool,
+    ) -> Result<PwsafeReader<R>> {
+        PwsafeReader::open(inner, Secret::Password(password, &mut progress), self)
+    }
 
-        if &tag != b"PWS3" {
-            return Err(Error::InvalidTag);
+    /// Checks whether `password` opens the database, see `check_password`.
+    ///
+    /// Fails with `ExcessiveIterations` above the limit set with `max_iterations`, the other
+    /// options do not apply.
+    pub fn check_password<R: Read>(&self, mut inner: R, password: &[u8]) -> Result<bool> {
+        let preamble = read_preamble_until(&mut inner, PreamblePart::TrueHash)?;
+        if preamble.iter > self.max_iterations {
+            return Err(Error::ExcessiveIterations(preamble.iter));
         }
+        let key = stretch_key(&preamble.salt, preamble.iter, password);
+        Ok(key_hash(&key)[..] == preamble.password_hash)
+    }
+
+    /// Creates a new `PwsafeReader` with a stretched key, see `PwsafeReader::from_key`.
+    pub fn open_with_key<R: Read>(&self, inner: R, key: &[u8; 32]) -> Result<PwsafeReader<R>> {
+        PwsafeReader::open(inner, Secret::Key(key), self)
+    }
+}
+
+/// Password or already stretched key used to open a database.
+enum Secret<'a> {
+    /// Password and key stretching progress callback
+    Password(&'a [u8], &'a mut dyn FnMut(u32, u32) -> bool),
+    Key(&'a [u8; 32]),
+}
+
+/// Opens a database and reads its header, leaving the reader at the first record.
+///
+/// Fails with `InvalidHeader` if the header has no version field. Use `PwsafeReader::new` for
+/// full control over reading.
+///
+/// ```rust
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+/// let (header, mut db) = pwsafer::open(file, b"password").unwrap();
+/// println!("Version is {:x?}", header.version());
+/// while let Some(record) = db.read_record().unwrap() {
+///     println!("{:?}", record.title());
+/// }
+/// db.verify().unwrap();
+/// ```
+pub fn open<R: Read>(inner: R, password: &[u8]) -> Result<(PwsafeHeader, PwsafeReader<R>)> {
+    read_header_checked(PwsafeReader::new(inner, password)?)
+}
+
+/// Reads the header of an opened database like `open`.
+pub(crate) fn read_header_checked<R>(mut reader: PwsafeReader<R>) -> Result<(PwsafeHeader, PwsafeReader<R>)> {
+    let header = reader.read_header()?;
+    if header.version().is_none() {
+        return Err(Error::InvalidHeader);
+    }
+    Ok((header, reader))
+}
+
+/// Returns the error for a file starting with `tag` instead of `PWS3`.
+pub(crate) fn check_gzip(tag: &[u8; 4]) -> Error {
+    if tag[..2] == GZIP_MAGIC {
+        Error::GzipCompressed
+    } else {
+        Error::InvalidTag
+    }
+}
+
+/// Parses a header field, adding the field type to errors.
+fn parse_header_field(field_type: u8, data: Vec<u8>) -> Result<PwsafeHeaderField> {
+    PwsafeHeaderField::new(field_type, data).map_err(|e| match HeaderFieldType::from_u8(field_type) {
+        Some(field_type) => Error::HeaderFieldError(field_type, e),
+        None => Error::FieldError(e),
+    })
+}
+
+impl<R: Read> PwsafeReader<R> {
+    /// Creates a new `PwsafeReader` with the given password and reads ps3db data into buffer.
+    ///
+    /// The password is checked right after reading the salt, iteration count and password hash.
+    /// On `InvalidPassword` nothing beyond those is read from `inner`. An empty password opens
+    /// databases created with one, see `PwsafeWriter::new`.
+    pub fn new(inner: R, password: &[u8]) -> Result<Self> {
+        PwsafeReaderBuilder::new().open(inner, password)
+    }
+
+    /// Creates a new `PwsafeReader` reading a database of known length, see
+    /// `PwsafeReaderBuilder::size_hint`.
+    ///
+    /// Useful for readers that do not know their length themselves, e.g. a decompressing
+    /// stream or a network connection with a `Content-Length`. `File` and byte slices already
+    /// size the buffer correctly with `new`, a `BufReader` around a `File` only adds a copy of
+    /// the preamble and can be left out.
+    ///
+    /// ```rust
+    /// use pwsafer::PwsafeReader;
+    /// use std::fs::File;
+    ///
+    /// let file = File::open("tests/pwsafe.psafe3").unwrap();
+    /// let len = file.metadata().unwrap().len();
+    /// let mut db = PwsafeReader::from_reader_exact(file, b"password", len).unwrap();
+    /// db.verify_all().unwrap();
+    /// ```
+    pub fn from_reader_exact(inner: R, password: &[u8], len: u64) -> Result<Self> {
+        PwsafeReaderBuilder::new().size_hint(len).open(inner, password)
+    }
+
+    /// Creates a new `PwsafeReader` whose key stretching can be cancelled from another thread.
+    ///
+    /// Fails with `Cancelled` soon after `cancel` is set, e.g. when the user closes an unlock
+    /// dialog. The flag is checked every 65536 iterations, see
+    /// `PwsafeReaderBuilder::open_with_progress`.
+    pub fn new_interruptible(inner: R, password: &[u8], cancel: &AtomicBool) -> Result<Self> {
+        PwsafeReaderBuilder::new().open_with_progress(inner, password, |_, _| !cancel.load(atomic::Ordering::Relaxed))
+    }
 
-        let mut salt = [0; 32];
-        inner.read_exact(&mut salt)?;
-        let iter = inner.read_u32::<LittleEndian>()?;
-        let mut truehash = [0; 32];
-        inner.read_exact(&mut truehash)?;
+    /// Creates a new `PwsafeReader` with a password given as a string, encoded as UTF-8.
+    ///
+    /// Password Safe encodes the password the way the platform does, which is UTF-8 on current
+    /// versions. Databases created by legacy versions with non-ASCII passwords in another
+    /// encoding fail with `InvalidPassword` here, pass the password bytes in the encoding of the
+    /// original application to `new` instead.
+    pub fn new_str(inner: R, password: &str) -> Result<Self> {
+        PwsafeReader::new(inner, password.as_bytes())
+    }
 
-        let mut k = [0u8; 32];
-        let mut l = [0u8; 32];
-        let mut iv = [0u8; 16];
-        inner.read_exact(&mut k)?;
-        inner.read_exact(&mut l)?;
-        inner.read_exact(&mut iv)?;
+    /// Creates a new `PwsafeReader` with a key stretched by `stretch_key` instead of the
+    /// password, skipping the slow key stretching.
+    ///
+    /// The key is only valid for the salt and iteration count of the database it was derived
+    /// for, see `get_salt` and `get_iter`. A wrong key results in `InvalidPassword`.
+    ///
+    /// Password Safe itself knows no key files. Applications adding their own unlocking scheme,
+    /// e.g. mixing a key file into the password, can derive the key themselves and pass it here
+    /// and to `PwsafeWriter::from_key`, at the cost of databases only they can open.
+    pub fn from_key(inner: R, key: &[u8; 32]) -> Result<Self> {
+        PwsafeReader::open(inner, Secret::Key(key), &PwsafeReaderBuilder::new())
+    }
 
-        let key = hash_password(&salt, iter, password);
+    fn open(mut inner: R, secret: Secret, options: &PwsafeReaderBuilder) -> Result<Self> {
+        let mut preamble = read_preamble_until(&mut inner, PreamblePart::TrueHash)?;
+        let (salt, iter) = (preamble.salt, preamble.iter);
 
-        let mut hasher = Sha256::default();
-        hasher.update(&key);
-        if hasher.finalize().as_slice() != truehash {
+        // Check the password before reading any further
+        let key = match secret {
+            Secret::Password(_, _) if iter > options.max_iterations => {
+                return Err(Error::ExcessiveIterations(iter))
+            }
+            Secret::Password(password, progress) => {
+                stretch_key_with_progress(&salt, iter, password, |done| progress(done, iter))
+                    .ok_or(Error::Cancelled)?
+            }
+            Secret::Key(key) => *key,
+        };
+
+        if key_hash(&key)[..] != preamble.password_hash {
             return Err(Error::InvalidPassword);
         }
-        
-        let twofish_cipher = Twofish::new_from_slice(&key).unwrap();
-        let mut ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &GenericArray::default());
-        ecb_cipher.decrypt(&mut k).unwrap();
-        ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &GenericArray::default());
-        ecb_cipher.decrypt(&mut l).unwrap();
 
-        let cbc_cipher = TwofishCbc::new_from_slices(&k, &iv).unwrap();
+        preamble.read_parts(&mut inner, PreamblePart::K..=PreamblePart::Iv)?;
+        let Preamble { mut k, mut l, iv, .. } = preamble;
+
+        let twofish_cipher = Twofish::new_from_slice(&key).map_err(|_| Error::InvalidCipherKey)?;
+        let mut ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &Default::default());
+        ecb_cipher.decrypt(&mut k).map_err(|_| Error::InvalidCipherKey)?;
+        ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &Default::default());
+        ecb_cipher.decrypt(&mut l).map_err(|_| Error::InvalidCipherKey)?;
+
+        let cbc_cipher = TwofishCbc::new_from_slices(&k, &iv).map_err(|_| Error::InvalidCipherKey)?;
+
+        let hmac = HmacSha256::new_from_slice(&l).map_err(|_| Error::InvalidCipherKey)?;
 
-        let hmac = HmacSha256::new_from_slice(&l).unwrap();
+        let capacity = options.size_hint.map_or(0, |len| len.min(MAX_SIZE_HINT).saturating_sub(PREAMBLE_LEN));
+        let mut buffer = Vec::with_capacity(capacity as usize);
+        inner.read_to_end(&mut buffer)?;
 
-        let mut buffer = Vec::new();
-        inner.read_to_end(&mut buffer).unwrap();
-        let mut eof_hmac = buffer[buffer.len()-48..buffer.len()].to_vec();   //48 because of pws3eof and hmac
-        buffer = buffer[0..buffer.len()-48].to_vec();
-        cbc_cipher.decrypt(&mut buffer).unwrap();
-        buffer.append(&mut eof_hmac);
+        // The EOF block is stored unencrypted right after the last encrypted block
+        let eof = buffer.chunks(16).position(|block| block == options.eof_sentinel);
+        let mac = match eof {
+            Some(eof) => {
+                let mac = buffer[eof * 16 + 16..].to_vec();
+                buffer.truncate(eof * 16);
+                mac
+            }
+            None if options.recovery => {
+                buffer.truncate(buffer.len() / 16 * 16);
+                Vec::new()
+            }
+            None => return Err(Error::MissingEof),
+        };
+        cbc_cipher.decrypt(&mut buffer).map_err(|_| Error::MissingEof)?;
+
+        // With wrongly unwrapped keys the first length is random and almost always too large
+        if !options.recovery && buffer.len() >= 16 {
+            let len = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+            if len.saturating_sub(11) > buffer.len() - 16 {
+                return Err(Error::KeyUnwrapFailed);
+            }
+        }
 
         Ok(PwsafeReader {
             _inner: inner,
             buffer: Cursor::new(buffer),
+            mac,
+            initial_hmac: hmac.clone(),
             hmac,
+            salt,
             iter,
+            in_header: true,
+            options: options.clone(),
+            skipped: false,
+            fields_read: 0,
+            #[cfg(feature = "debug-hmac")]
+            hmac_snapshots: Vec::new(),
+            #[cfg(feature = "test-vectors")]
+            keys: KeyMaterial { k, l, iv },
         })
     }
+}
+
+#[cfg(feature = "flate2")]
+impl<R: Read> PwsafeReader<GzDecoder<R>> {
+    /// Creates a new `PwsafeReader` reading a gzip compressed database, e.g. a `.psafe3.gz`
+    /// backup.
+    ///
+    /// The stream is decompressed while reading the database into the buffer, see `new`.
+    /// Fails with `InvalidTag` if `inner` is not gzip compressed or does not decompress to a
+    /// database.
+    pub fn new_gz(inner: R, password: &[u8]) -> Result<Self> {
+        PwsafeReader::new(GzDecoder::new(inner), password)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin> PwsafeReader<R> {
+    /// Creates a new `PwsafeReader` reading ps3db data from an asynchronous reader.
+    ///
+    /// Only reading the database is asynchronous. Key stretching and decryption run on the
+    /// current task, so with high iteration counts consider calling this from `spawn_blocking`.
+    pub async fn new_async(mut inner: R, password: &[u8]) -> Result<Self> {
+        use tokio::io::AsyncReadExt;
+
+        let mut data = Vec::new();
+        inner.read_to_end(&mut data).await?;
+        let reader = PwsafeReader::new(Cursor::new(data), password)?;
+        Ok(reader.with_inner(inner))
+    }
+}
+
+impl<R> PwsafeReader<R> {
+    /// Replaces the underlying reader, keeping the decrypted data.
+    fn with_inner<T>(self, inner: T) -> PwsafeReader<T> {
+        PwsafeReader {
+            _inner: inner,
+            buffer: self.buffer,
+            mac: self.mac,
+            hmac: self.hmac,
+            initial_hmac: self.initial_hmac,
+            salt: self.salt,
+            iter: self.iter,
+            in_header: self.in_header,
+            options: self.options,
+            skipped: self.skipped,
+            fields_read: self.fields_read,
+            #[cfg(feature = "debug-hmac")]
+            hmac_snapshots: self.hmac_snapshots,
+            #[cfg(feature = "test-vectors")]
+            keys: self.keys,
+        }
+    }
 
     /// Reads the database version field.
+    ///
+    /// Must be the first call after opening the database, returns `OutOfOrder` once any field
+    /// was read and `InvalidHeader` if the first field is not a valid version field.
     pub fn read_version(&mut self) -> Result<u16> {
-        let (field_type, data) = self.read_field()?.unwrap();
+        if self.buffer.position() != 0 {
+            return Err(Error::OutOfOrder);
+        }
+        let (field_type, data) = self.read_field()?.ok_or(Error::InvalidHeader)?;
         let field = PwsafeHeaderField::new(field_type, data);
         if let Ok(PwsafeHeaderField::Version(version)) = field {
             return Ok(version);
@@ -152,51 +669,423 @@ impl<R: Read> PwsafeReader<R> {
         Err(Error::InvalidHeader)
     }
 
+    /// Opens the next database following this one in the same stream, e.g. in an archive of
+    /// concatenated databases.
+    ///
+    /// The next database starts right after the HMAC and is opened with the same options and
+    /// the given password. Returns `None` if nothing follows the HMAC. Call `verify` before,
+    /// this reader is consumed.
+    pub fn next_database(mut self, password: &[u8]) -> Result<Option<Self>> {
+        if self.mac.len() <= 32 {
+            return Ok(None);
+        }
+        let rest = self.mac.split_off(32);
+        let next = self.options.open(Cursor::new(rest), password)?;
+        Ok(Some(next.with_inner(self._inner)))
+    }
+
     /// Reads a field.
     ///
     /// Returns field type and contents or `None` if EOF block is encountered.
     pub fn read_field(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
-        let mut block = [0u8; 16];
-        self.buffer.read_exact(&mut block)?;
+        let field = self.next_field()?;
+        Ok(field.map(|(field_type, range)| (field_type, self.buffer.get_ref()[range].to_vec())))
+    }
+
+    /// Calls `f` with type and contents of each remaining field until the EOF block.
+    ///
+    /// The contents are borrowed from the decrypted data, no field is copied. Stops at the first
+    /// error, including one returned by `f`. The fields go through the HMAC like with
+    /// `read_field`, so `verify` can be called afterwards.
+    pub fn visit_fields<F: FnMut(u8, &[u8]) -> Result<()>>(&mut self, mut f: F) -> Result<()> {
+        while let Some((field_type, range)) = self.next_field()? {
+            f(field_type, &self.buffer.get_ref()[range])?;
+        }
+        Ok(())
+    }
 
-        let eof = b"PWS3-EOFPWS3-EOF";
-        if &block == eof {
+    /// Reads the next field, returns its type and the position of its contents in the buffer.
+    ///
+    /// The field contents follow the type in the first block and continue through the next
+    /// blocks, so they are contiguous in the decrypted data.
+    fn next_field(&mut self) -> Result<Option<(u8, Range<usize>)>> {
+        let mut block = [0u8; 16];
+        if self.buffer.position() as usize == self.buffer.get_ref().len() {
+            if self.options.clear_on_eof {
+                self.clear_buffer();
+            }
             return Ok(None);
         }
+        let start = self.buffer.position() as usize + 5;
+        self.buffer.read_exact(&mut block)?;
 
         let mut cursor = Cursor::new(&block);
-        let field_length = cursor.read_u32::<LittleEndian>().unwrap() as usize;
-        let field_type = cursor.read_u8().unwrap();
+        let field_length = cursor.read_u32::<LittleEndian>()? as usize;
+        let field_type = cursor.read_u8()?;
+        self.check_field_type(field_type)?;
 
-        let mut data = Vec::new();
-        data.reserve(field_length);
-        data.extend_from_slice(&block[5..5 + min(11, field_length)]);
+        // Refuse lengths that run past the end of the data
+        let remaining = (self.buffer.get_ref().len() - self.buffer.position() as usize) as u64;
+        let rest = field_length.saturating_sub(11) as u64;
+        if rest.div_ceil(16) * 16 > remaining {
+            if self.options.recovery {
+                self.buffer.set_position(self.buffer.get_ref().len() as u64);
+                self.skipped = true;
+                return Ok(None);
+            }
+            return Err(Error::InvalidFieldLength);
+        }
+
+        // Skip the rest of the field
+        let position = self.buffer.position() + rest.div_ceil(16) * 16;
+        self.buffer.set_position(position);
+        let range = start..start + field_length;
+        self.hmac.update(&self.buffer.get_ref()[range.clone()]);
+        #[cfg(feature = "debug-hmac")]
+        self.hmac_snapshots.push(self.hmac.clone().finalize().into_bytes().into());
+        if field_type == HeaderFieldType::EndOfHeader.as_u8() {
+            self.in_header = false;
+        }
+        self.fields_read += 1;
+
+        Ok(Some((field_type, range)))
+    }
+
+    /// Rejects reserved and unknown field types if the options ask for it.
+    fn check_field_type(&self, field_type: u8) -> Result<()> {
+        if self.options.reject_reserved {
+            let reserved = if self.in_header {
+                HeaderFieldType::is_reserved(field_type)
+            } else {
+                RecordFieldType::is_reserved(field_type)
+            };
+            if reserved {
+                return Err(Error::ReservedFieldType(field_type));
+            }
+        }
+
+        if self.options.strict {
+            let known = if self.in_header {
+                HeaderFieldType::from_u8(field_type).is_some()
+            } else {
+                RecordFieldType::from_u8(field_type).is_some()
+            };
+            if !known {
+                return Err(Error::UnknownFieldType(field_type));
+            }
+        }
+        Ok(())
+    }
 
-        // Read the rest of the field
-        let mut i = 11;
-        while i < field_length {
-            self.buffer.read_exact(&mut block)?;
-            data.extend_from_slice(&block[0..min(16, field_length - i)]);
-            i += 16;
+    /// Returns `true` until the end of header field was read.
+    pub(crate) fn in_header(&self) -> bool {
+        self.in_header
+    }
+
+    /// Returns the number of fields read so far by any method, including end of header and end
+    /// of record fields.
+    ///
+    /// The count only grows over the lifetime of the reader, it is not reset by any method. A UI
+    /// can show it while reading a large database, `verify_with_progress` reports progress in
+    /// bytes instead.
+    pub fn fields_read(&self) -> usize {
+        self.fields_read
+    }
+
+    /// Reads a field like `read_field`, checking the HMAC as soon as the EOF block is reached.
+    ///
+    /// Returns `None` only if the database integrity was verified, so a loop over this method
+    /// can not forget to call `verify`. Fields returned before the error are not authenticated
+    /// yet, do not act on them until the loop has ended successfully.
+    pub fn read_field_verified(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        let field = self.read_field()?;
+        if field.is_none() {
+            self.verify()?;
         }
-        self.hmac.update(&data);
+        Ok(field)
+    }
+
+    /// Returns the type of the next field without consuming it.
+    ///
+    /// Returns `None` if EOF block is encountered. The following `read_field` returns the same
+    /// field. Fails with `ReservedFieldType` or `UnknownFieldType` like `read_field` would, see
+    /// `PwsafeReaderBuilder::reject_reserved` and `PwsafeReaderBuilder::strict`.
+    pub fn peek_field_type(&mut self) -> Result<Option<u8>> {
+        let pos = self.buffer.position() as usize;
+        match self.buffer.get_ref().get(pos + 4) {
+            Some(&field_type) => {
+                self.check_field_type(field_type)?;
+                Ok(Some(field_type))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads and parses the header fields up to and including the end of header field.
+    ///
+    /// Includes the version field unless it was already read with `read_version`.
+    pub fn read_header(&mut self) -> Result<PwsafeHeader> {
+        let mut fields = Vec::new();
+        loop {
+            let (field_type, data) = self.read_field()?.ok_or(Error::InvalidHeader)?;
+            match parse_header_field(field_type, data)? {
+                PwsafeHeaderField::EndOfHeader => break,
+                field => fields.push(field),
+            }
+        }
+        Ok(PwsafeHeader::new(fields))
+    }
 
-        assert_eq!(data.len(), field_length);
-        Ok(Some((field_type, data)))
+    /// Returns an iterator over the remaining raw fields, calling `read_field` until the EOF
+    /// block.
+    ///
+    /// Ends after the first error.
+    ///
+    /// ```rust
+    /// use pwsafer::PwsafeReader;
+    /// use std::fs::File;
+    ///
+    /// let file = File::open("tests/pwsafe.psafe3").unwrap();
+    /// let mut db = PwsafeReader::new(file, b"password").unwrap();
+    /// let titles = db.fields().filter(|f| matches!(f, Ok((0x03, _)))).count();
+    /// assert_eq!(titles, 1);
+    /// db.verify().unwrap();
+    /// ```
+    pub fn fields(&mut self) -> impl Iterator<Item = Result<(u8, Vec<u8>)>> + '_ {
+        let mut done = false;
+        iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let res = self.read_field().transpose();
+            done = !matches!(res, Some(Ok(_)));
+            res
+        })
+    }
+
+    /// Returns an iterator parsing the remaining header fields.
+    ///
+    /// Includes the version field unless it was already read with `read_version`. Ends after
+    /// the end of header field, which is not returned, or after the first error.
+    pub fn typed_header_fields(&mut self) -> impl Iterator<Item = Result<PwsafeHeaderField>> + '_ {
+        let mut done = false;
+        iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let res = match self.read_field() {
+                Ok(Some((field_type, data))) => parse_header_field(field_type, data),
+                Ok(None) => Err(Error::InvalidHeader),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(PwsafeHeaderField::EndOfHeader) => {
+                    done = true;
+                    None
+                }
+                Ok(field) => Some(Ok(field)),
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Returns an iterator parsing the remaining fields as record fields.
+    ///
+    /// Must be used after reading the header. End of record fields are returned to separate the
+    /// records. Ends at the EOF block or after the first error.
+    pub fn typed_fields(&mut self) -> impl Iterator<Item = Result<PwsafeRecordField>> + '_ {
+        let mut done = false;
+        iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let res = match self.read_field() {
+                Ok(Some((field_type, data))) => PwsafeRecordField::new(field_type, data).map_err(Error::from),
+                Ok(None) => return None,
+                Err(e) => Err(e),
+            };
+            done = res.is_err();
+            Some(res)
+        })
+    }
+
+    /// Returns an iterator parsing the remaining fields as header fields up to and including
+    /// the end of header marker and as record fields after it.
+    ///
+    /// End of header and end of record fields are returned. Ends at the EOF block or after the
+    /// first error.
+    pub fn all_fields(&mut self) -> impl Iterator<Item = Result<AnyField>> + '_ {
+        let mut done = false;
+        iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let in_header = self.in_header;
+            let res = match self.read_field() {
+                Ok(Some((field_type, data))) if in_header => parse_header_field(field_type, data).map(AnyField::Header),
+                Ok(Some((field_type, data))) => PwsafeRecordField::new(field_type, data)
+                    .map(AnyField::Record)
+                    .map_err(Error::from),
+                Ok(None) => return None,
+                Err(e) => Err(e),
+            };
+            done = res.is_err();
+            Some(res)
+        })
+    }
+
+    /// Reads the header and returns its name, description, last save time and version.
+    ///
+    /// Stops after the end of header marker, records are left unread. Returns `InvalidHeader` if
+    /// there is no version field.
+    pub fn metadata(&mut self) -> Result<DatabaseMetadata> {
+        let header = self.read_header()?;
+        DatabaseMetadata::from_header(&header).ok_or(Error::InvalidHeader)
+    }
+
+    /// Reads and parses the fields of the next record up to and including the end of record
+    /// field.
+    ///
+    /// Returns `None` if EOF block is encountered. Must be called after reading the header.
+    pub fn read_record(&mut self) -> Result<Option<PwsafeRecord>> {
+        let mut fields = Vec::new();
+        loop {
+            let (field_type, data) = match self.read_field()? {
+                Some(field) => field,
+                None if fields.is_empty() => return Ok(None),
+                None => return Err(Error::InvalidRecord),
+            };
+            match PwsafeRecordField::new(field_type, data)? {
+                PwsafeRecordField::EndOfRecord => break,
+                field => fields.push(field),
+            }
+        }
+        Ok(Some(PwsafeRecord::new(fields)))
     }
 
-    /// Reads HMAC and checks the database integrity.
+    /// Checks the database integrity against the HMAC stored after the EOF block.
     ///
-    /// This function must be called after reading the last field in the database.
+    /// This function must be called after reading the last field in the database. In recovery
+    /// mode it fails with `InvalidFieldLength` if a corrupted field was skipped.
     pub fn verify(&mut self) -> Result<()> {
-        let mut mac = [0u8; 32];
-        self.buffer.read_exact(&mut mac)?;
-        self.hmac.clone().verify(&mac)?;
+        if self.skipped {
+            return Err(Error::InvalidFieldLength);
+        }
+        if self.mac.len() < 32 {
+            return Err(Error::InvalidMacLength);
+        }
+        self.hmac.clone().verify(&self.mac[..32])?;
         Ok(())
     }
 
+    /// Computes the HMAC of a database with the given field contents using this database's HMAC
+    /// key.
+    ///
+    /// The HMAC covers the contents of every field in order, including end of header and end of
+    /// record fields, but not their types and lengths. An editor keeping decrypted fields in
+    /// memory can check its own changes against what a writer would produce.
+    ///
+    /// Keeping the plaintext fields around exposes them to swapping, core dumps and other
+    /// processes able to read memory. Drop them as soon as possible.
+    pub fn compute_mac<'a, I: IntoIterator<Item = &'a [u8]>>(&self, fields: I) -> [u8; 32] {
+        let mut hmac = self.initial_hmac.clone();
+        for data in fields {
+            hmac.update(data);
+        }
+        hmac.finalize().into_bytes().into()
+    }
+
+    /// Returns the key of record MACs of this database, see `PwsafeRecord::set_record_mac`.
+    ///
+    /// Derived from the database HMAC key, it can not be used to recover that key.
+    pub fn record_mac_key(&self) -> [u8; 32] {
+        record_mac_key(&self.initial_hmac)
+    }
+
+    /// Reads all remaining fields and checks the database integrity.
+    ///
+    /// Unlike `verify` this can be called at any point. It consumes the rest of the stream, so
+    /// fields not read so far can not be read afterwards.
+    pub fn verify_all(&mut self) -> Result<()> {
+        self.verify_with_progress(|_| ())
+    }
+
+    /// Like `verify_all`, calling `progress` after each field with the number of decrypted bytes
+    /// processed so far.
+    ///
+    /// The total is `decrypted_len`, so a UI can show the fraction done.
+    pub fn verify_with_progress(&mut self, mut progress: impl FnMut(u64)) -> Result<()> {
+        while self.read_field()?.is_some() {
+            progress(self.buffer.position());
+        }
+        self.verify()
+    }
+
+    /// Reads all remaining fields, header and records alike, and counts them by field type.
+    ///
+    /// Shows which vendor extensions a database uses. The fields still go through the HMAC, so
+    /// `verify` can be called afterwards.
+    pub fn field_type_histogram(&mut self) -> Result<BTreeMap<u8, usize>> {
+        let mut histogram = BTreeMap::new();
+        while let Some((field_type, _)) = self.read_field()? {
+            *histogram.entry(field_type).or_insert(0) += 1;
+        }
+        Ok(histogram)
+    }
+
+    /// Overwrites the decrypted field data with zeros and releases it.
+    ///
+    /// Call this once all fields were read to limit how long the plaintext stays in memory.
+    /// Afterwards `read_field` returns `None` and `decrypted_len` is 0, the fields can not be
+    /// read again. `verify` is unaffected. Copies made by the caller, e.g. the field contents
+    /// returned by `read_field`, are not touched.
+    pub fn clear_buffer(&mut self) {
+        let mut buffer = std::mem::take(self.buffer.get_mut());
+        zeroize(&mut buffer);
+        self.buffer.set_position(0);
+    }
+
+    /// Returns the whole decrypted field stream, including padding and fields already read.
+    ///
+    /// Neither the EOF block nor the HMAC are included. Exposes every secret in the database,
+    /// only meant for debugging and format research.
+    #[cfg(feature = "debug-plaintext")]
+    pub fn decrypted_bytes(&self) -> &[u8] {
+        self.buffer.get_ref()
+    }
+
+    /// Returns the running HMAC after each field read so far.
+    ///
+    /// Comparing against `PwsafeWriter::hmac_snapshots` of the writer that produced the database
+    /// shows the first field whose contents differ.
+    #[cfg(feature = "debug-hmac")]
+    pub fn hmac_snapshots(&self) -> &[[u8; 32]] {
+        &self.hmac_snapshots
+    }
+
+    /// Returns the length of the decrypted field data in bytes.
+    pub fn decrypted_len(&self) -> u64 {
+        self.buffer.get_ref().len() as u64
+    }
+
     /// Returns the number of iterations used for key stretching.
     pub fn get_iter(&self) -> u32 {
         self.iter
     }
+
+    /// Returns the salt used for key stretching.
+    pub fn get_salt(&self) -> &[u8; 32] {
+        &self.salt
+    }
+
+    /// Returns the plaintext subkeys and initial IV.
+    ///
+    /// Leaks key material, only meant for conformance tests.
+    #[cfg(feature = "test-vectors")]
+    pub fn key_material(&self) -> &KeyMaterial {
+        &self.keys
+    }
 }
@@ -0,0 +1,530 @@
+use crate::base32;
+use crate::field::{Error, PwsafeRecordField, RecordFieldType, Result};
+use crate::history::PasswordHistory;
+use crate::policy::{NamedPasswordPolicy, PasswordPolicy};
+use crate::writer::PADDING_FIELD_TYPE;
+use hmac::{Hmac, Mac, NewMac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds in a day, tolerated clock skew for timestamps in the future.
+const DAY: u32 = 24 * 60 * 60;
+
+/// Type of the field holding a record MAC, see `PwsafeRecord::set_record_mac`.
+const RECORD_MAC_FIELD_TYPE: u8 = 0xdd;
+
+/// Returns the current time in seconds since the Unix epoch.
+fn unix_time() -> u32 {
+    to_unix_time(SystemTime::now())
+}
+
+/// Converts a time to seconds since the Unix epoch, earlier times become 0.
+fn to_unix_time(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+/// Returns a random (version 4) UUID.
+pub(crate) fn random_uuid(rng: &mut impl RngCore) -> [u8; 16] {
+    let mut uuid = [0u8; 16];
+    rng.fill_bytes(&mut uuid);
+    uuid[6] = (uuid[6] & 0x0f) | 0x40; // Version 4 (random)
+    uuid[8] = (uuid[8] & 0x3f) | 0x80; // RFC 4122 variant
+    uuid
+}
+
+/// Replaces the record MAC among the raw fields of a record with one under `new_key` if it
+/// verifies under `old_key`, see `PwsafeRecord::set_record_mac`.
+///
+/// A MAC that does not verify is left as it is, so the record keeps failing verification.
+pub(crate) fn rekey_record_mac(fields: &mut [(u8, Vec<u8>)], old_key: &[u8; 32], new_key: &[u8; 32]) {
+    let pos = match fields.iter().position(|&(field_type, _)| field_type == RECORD_MAC_FIELD_TYPE) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let parsed: Result<Vec<_>> = fields
+        .iter()
+        .filter(|&&(field_type, _)| field_type != RecordFieldType::EndOfRecord.as_u8())
+        .map(|(field_type, data)| PwsafeRecordField::new(*field_type, data.clone()))
+        .collect();
+    let mut record = match parsed {
+        Ok(fields) => PwsafeRecord::new(fields),
+        Err(_) => return,
+    };
+    if record.verify_record_mac(old_key) == Some(true) {
+        record.set_record_mac(new_key);
+        fields[pos].1 = record.record_mac_data().unwrap().to_vec();
+    }
+}
+
+/// Password Safe record, the fields of a single entry without the end of record marker.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PwsafeRecord {
+    fields: Vec<PwsafeRecordField>,
+}
+
+impl PwsafeRecord {
+    /// Creates a record from its fields.
+    pub fn new(fields: Vec<PwsafeRecordField>) -> Self {
+        PwsafeRecord { fields }
+    }
+
+    /// Returns the fields of the record.
+    pub fn fields(&self) -> &[PwsafeRecordField] {
+        &self.fields
+    }
+
+    /// Returns the fields of the record for modification.
+    pub fn fields_mut(&mut self) -> &mut Vec<PwsafeRecordField> {
+        &mut self.fields
+    }
+
+    /// Consumes the record, returning its fields.
+    pub fn into_fields(self) -> Vec<PwsafeRecordField> {
+        self.fields
+    }
+
+    /// Sorts the fields into the order used by Password Safe.
+    ///
+    /// The UUID comes first, followed by the other known fields ordered by their type byte.
+    /// Unknown fields are moved to the end, keeping their relative order.
+    pub fn sort_fields(&mut self) {
+        self.fields.sort_by_cached_key(|field| match field {
+            PwsafeRecordField::Uuid(_) => (0, 0),
+            PwsafeRecordField::Blob { .. } => (2, 0),
+            PwsafeRecordField::EndOfRecord => (3, 0),
+            field => (1, field.field_type()),
+        });
+    }
+
+    /// Returns the UUID of the entry.
+    pub fn uuid(&self) -> Option<[u8; 16]> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Uuid(uuid) => Some(*uuid),
+            _ => None,
+        })
+    }
+
+    /// Returns the UUID of the entry as a `uuid::Uuid`.
+    #[cfg(feature = "uuid")]
+    pub fn uuid_typed(&self) -> Option<uuid::Uuid> {
+        self.uuid().map(uuid::Uuid::from_bytes)
+    }
+
+    /// Returns the group.
+    pub fn group(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Group(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the title.
+    pub fn title(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Title(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the username.
+    pub fn username(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Username(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the password.
+    pub fn password(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Password(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the notes.
+    pub fn notes(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Notes(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the URL.
+    pub fn url(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Url(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the email address.
+    pub fn email(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::EmailAddress(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the raw two-factor (TOTP) secret.
+    pub fn two_factor_key(&self) -> Option<&[u8]> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::TwoFactorKey(key) => Some(key.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Returns the two-factor (TOTP) secret encoded as unpadded base32, the format
+    /// authenticator apps display and accept.
+    pub fn two_factor_key_base32(&self) -> Option<String> {
+        self.two_factor_key().map(base32::encode)
+    }
+
+    /// Returns a SHA-256 hash of the logical content of the entry, to find identical entries
+    /// across databases.
+    ///
+    /// Covers group, title, username, password, URL and notes in this order, each prefixed with
+    /// its length. A missing field hashes like an empty one. UUID, timestamps and all other
+    /// fields are ignored.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut content = [""; 6];
+        for field in &self.fields {
+            let (i, s) = match field {
+                PwsafeRecordField::Group(s) => (0, s),
+                PwsafeRecordField::Title(s) => (1, s),
+                PwsafeRecordField::Username(s) => (2, s),
+                PwsafeRecordField::Password(s) => (3, s),
+                PwsafeRecordField::Url(s) => (4, s),
+                PwsafeRecordField::Notes(s) => (5, s),
+                _ => continue,
+            };
+            if content[i].is_empty() {
+                content[i] = s.as_str();
+            }
+        }
+        let mut hasher = Sha256::default();
+        for s in &content {
+            hasher.update((s.len() as u64).to_le_bytes());
+            hasher.update(s.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Checks the timestamps of the record for values that can not be right.
+    ///
+    /// Fails with `InvalidTimestamp` naming the first offending field for zero timestamps,
+    /// timestamps more than a day in the future, password expiry times past 2038 and a creation
+    /// time after the last modification time.
+    pub fn validate_timestamps(&self) -> Result<()> {
+        let now = unix_time();
+        let mut creation = None;
+        let mut modification = None;
+        for field in &self.fields {
+            let (field_type, timestamp, bound) = match *field {
+                PwsafeRecordField::CreationTime(t) => {
+                    creation = Some(t);
+                    (RecordFieldType::CreationTime, t, now.saturating_add(DAY))
+                }
+                PwsafeRecordField::LastModificationTime(t) => {
+                    modification = Some(t);
+                    (RecordFieldType::LastModificationTime, t, now.saturating_add(DAY))
+                }
+                PwsafeRecordField::PasswordModificationTime(t) => {
+                    (RecordFieldType::PasswordModificationTime, t, now.saturating_add(DAY))
+                }
+                PwsafeRecordField::LastAccessTime(t) => {
+                    (RecordFieldType::LastAccessTime, t, now.saturating_add(DAY))
+                }
+                PwsafeRecordField::PasswordExpiryTime(t) => {
+                    (RecordFieldType::PasswordExpiryTime, t, i32::MAX as u32)
+                }
+                _ => continue,
+            };
+            if timestamp == 0 || timestamp > bound {
+                return Err(Error::InvalidTimestamp(field_type));
+            }
+        }
+        if let (Some(creation), Some(modification)) = (creation, modification) {
+            if creation > modification {
+                return Err(Error::InvalidTimestamp(RecordFieldType::CreationTime));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the password policy that applies to the entry.
+    ///
+    /// An inline `PasswordPolicy` field takes precedence, its symbols are taken from the
+    /// `OwnSymbolsForPassword` field. Otherwise the policy referenced by `PasswordPolicyName` is
+    /// looked up in `named`. Returns `None` if neither applies, meaning the database default
+    /// policy is in effect.
+    pub fn effective_policy(&self, named: &[NamedPasswordPolicy]) -> Option<PasswordPolicy> {
+        let inline = self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::PasswordPolicy(s) => PasswordPolicy::parse(s).ok(),
+            _ => None,
+        });
+        if let Some(mut policy) = inline {
+            let symbols = self.fields.iter().find_map(|field| match field {
+                PwsafeRecordField::OwnSymbolsForPassword(s) => Some(s),
+                _ => None,
+            });
+            if let Some(symbols) = symbols {
+                policy.symbols = symbols.clone();
+            }
+            return Some(policy);
+        }
+
+        let name = self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::PasswordPolicyName(s) => Some(s),
+            _ => None,
+        })?;
+        named
+            .iter()
+            .find(|p| &p.name == name)
+            .map(|p| p.policy.clone())
+    }
+
+    /// Returns the password history, `None` if the entry has no `PasswordHistory` field.
+    pub fn password_history(&self) -> Result<Option<PasswordHistory>> {
+        self.fields
+            .iter()
+            .find_map(|field| match field {
+                PwsafeRecordField::PasswordHistory(s) => Some(PasswordHistory::parse(s)),
+                _ => None,
+            })
+            .transpose()
+    }
+
+    /// Adds `old` to the password history as replaced at `when`.
+    ///
+    /// Like Password Safe, the password becomes the newest entry and the oldest entries beyond
+    /// the maximum are dropped. Nothing is recorded if the entry has no `PasswordHistory` field
+    /// or keeping the history is disabled in it.
+    pub fn push_password_history(&mut self, old: &str, when: SystemTime) -> Result<()> {
+        if let Some(mut history) = self.password_history()? {
+            if history.enabled {
+                history.push(old, to_unix_time(when));
+                self.set_field(PwsafeRecordField::PasswordHistory(history.encode()?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the password, adding the previous one to the password history.
+    ///
+    /// Sets password modification and last modification times to now. See
+    /// `push_password_history`.
+    pub fn set_password(&mut self, password: &str) -> Result<()> {
+        let old = self.password().map(str::to_string);
+        if let Some(old) = old {
+            if old == password {
+                return Ok(());
+            }
+            self.push_password_history(&old, SystemTime::now())?;
+        }
+        let now = unix_time();
+        self.set_field(PwsafeRecordField::Password(password.to_string()));
+        self.set_field(PwsafeRecordField::PasswordModificationTime(now));
+        self.set_field(PwsafeRecordField::LastModificationTime(now));
+        Ok(())
+    }
+
+    /// Splits the fields into the ones this crate knows and the raw type and contents of
+    /// unknown ones, parsed as `Blob`s, both in record order.
+    pub fn partition_fields(&self) -> (Vec<PwsafeRecordField>, Vec<(u8, Vec<u8>)>) {
+        let mut known = Vec::new();
+        let mut unknown = Vec::new();
+        for field in &self.fields {
+            match field {
+                PwsafeRecordField::Blob { field_type, data } => unknown.push((*field_type, data.clone())),
+                field => known.push(field.clone()),
+            }
+        }
+        (known, unknown)
+    }
+
+    /// Sets the last access time to `now`, adding the field if missing.
+    ///
+    /// Password Safe does this whenever an entry is viewed or its password copied.
+    pub fn touch(&mut self, now: SystemTime) {
+        self.set_field(PwsafeRecordField::LastAccessTime(to_unix_time(now)));
+    }
+
+    /// Applies a partial update: each field replaces the field of the same type in place or is
+    /// appended, all other fields are kept.
+    ///
+    /// Types are compared by their type byte, so a `Blob` replaces the unknown field of the same
+    /// type. Does not touch modification times.
+    pub fn merge_fields(&mut self, updates: Vec<PwsafeRecordField>) {
+        for field in updates {
+            self.set_field(field);
+        }
+    }
+
+    /// Adds a MAC over all other fields of the record, replacing an existing one.
+    ///
+    /// Record MACs are a nonstandard extension of this crate localizing tampering or corruption
+    /// to single records, the HMAC of the file only tells that something changed. The MAC is an
+    /// HMAC-SHA-256 over type, length and contents of the fields in canonical order, see
+    /// `sort_fields`, stored in a record field of type 0xdd which the specification does not
+    /// define. Padding fields, see `PwsafeWriter::set_record_padding`, are not covered.
+    ///
+    /// Password Safe keeps the field as an unknown one but does not update it, so a record
+    /// edited there no longer verifies. Only this crate can check the MACs. The key comes from
+    /// `PwsafeReader::record_mac_key` or `PwsafeWriter::record_mac_key` and changes with the
+    /// database keys on every save. `PwsafeDatabase::save`, `rekdf`, `rotate_many` and
+    /// `PwsafeWriter::copy_fields` carry the MACs over to the new key, a save of Password Safe
+    /// does not.
+    pub fn set_record_mac(&mut self, key: &[u8; 32]) {
+        let mac = self.record_mac(key).finalize().into_bytes().to_vec();
+        self.set_field(PwsafeRecordField::Blob {
+            field_type: RECORD_MAC_FIELD_TYPE,
+            data: mac,
+        });
+    }
+
+    /// Checks the record MAC, see `set_record_mac`.
+    ///
+    /// Returns `None` if the record has no MAC.
+    pub fn verify_record_mac(&self, key: &[u8; 32]) -> Option<bool> {
+        let mac = self.record_mac_data()?;
+        Some(self.record_mac(key).verify(mac).is_ok())
+    }
+
+    /// Returns the contents of the record MAC field.
+    fn record_mac_data(&self) -> Option<&[u8]> {
+        self.fields.iter().find_map(|field| match field {
+            PwsafeRecordField::Blob { field_type, data } if *field_type == RECORD_MAC_FIELD_TYPE => Some(&data[..]),
+            _ => None,
+        })
+    }
+
+    /// Returns the HMAC over the fields covered by the record MAC, see `set_record_mac`.
+    fn record_mac(&self, key: &[u8; 32]) -> Hmac<Sha256> {
+        let mut record = self.clone();
+        record.fields.retain(|field| {
+            !matches!(field, PwsafeRecordField::Blob { field_type, .. }
+                if *field_type == RECORD_MAC_FIELD_TYPE || *field_type == PADDING_FIELD_TYPE)
+        });
+        record.sort_fields();
+        let mut hmac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        for field in &record.fields {
+            let (field_type, data) = field.to_field();
+            hmac.update(&[field_type]);
+            hmac.update(&(data.len() as u64).to_le_bytes());
+            hmac.update(&data);
+        }
+        hmac
+    }
+
+    /// Replaces the field of the same type in place or appends the field.
+    fn set_field(&mut self, field: PwsafeRecordField) {
+        let field_type = field.field_type();
+        match self.fields.iter_mut().find(|f| f.field_type() == field_type) {
+            Some(f) => *f = field,
+            None => self.fields.push(field),
+        }
+    }
+}
+
+/// Builder for a new `PwsafeRecord`.
+///
+/// ```rust
+/// use pwsafer::PwsafeRecordBuilder;
+///
+/// let record = PwsafeRecordBuilder::new()
+///     .title("Mail")
+///     .username("alice")
+///     .password("secret")
+///     .group("Internet.Mail")
+///     .url("https://mail.example")
+///     .build()
+///     .unwrap();
+/// assert_eq!(record.title(), Some("Mail"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PwsafeRecordBuilder {
+    fields: Vec<PwsafeRecordField>,
+}
+
+impl PwsafeRecordBuilder {
+    /// Creates a builder without any fields.
+    pub fn new() -> Self {
+        PwsafeRecordBuilder::default()
+    }
+
+    /// Sets the title.
+    pub fn title(self, title: &str) -> Self {
+        self.field(PwsafeRecordField::Title(title.to_string()))
+    }
+
+    /// Sets the username.
+    pub fn username(self, username: &str) -> Self {
+        self.field(PwsafeRecordField::Username(username.to_string()))
+    }
+
+    /// Sets the password.
+    pub fn password(self, password: &str) -> Self {
+        self.field(PwsafeRecordField::Password(password.to_string()))
+    }
+
+    /// Sets the group, its levels separated by dots.
+    pub fn group(self, group: &str) -> Self {
+        self.field(PwsafeRecordField::Group(group.to_string()))
+    }
+
+    /// Sets the URL.
+    pub fn url(self, url: &str) -> Self {
+        self.field(PwsafeRecordField::Url(url.to_string()))
+    }
+
+    /// Sets the notes.
+    pub fn notes(self, notes: &str) -> Self {
+        self.field(PwsafeRecordField::Notes(notes.to_string()))
+    }
+
+    /// Sets any other field, replacing a field of the same type.
+    pub fn field(mut self, field: PwsafeRecordField) -> Self {
+        let field_type = field.field_type();
+        self.fields.retain(|f| f.field_type() != field_type);
+        self.fields.push(field);
+        self
+    }
+
+    /// Builds the record.
+    ///
+    /// Adds a random UUID and sets creation, password modification and last modification times
+    /// to now unless they were set. Title and password are mandatory, returns `MissingField` if
+    /// either is missing.
+    pub fn build(self) -> Result<PwsafeRecord> {
+        for &field_type in &[RecordFieldType::Title, RecordFieldType::Password] {
+            if !self.has(field_type) {
+                return Err(Error::MissingField(field_type));
+            }
+        }
+        let now = unix_time();
+        let mut builder = self;
+        if !builder.has(RecordFieldType::Uuid) {
+            builder = builder.field(PwsafeRecordField::Uuid(random_uuid(&mut OsRng)));
+        }
+        for &(field_type, field) in &[
+            (RecordFieldType::CreationTime, PwsafeRecordField::CreationTime as fn(u32) -> _),
+            (RecordFieldType::PasswordModificationTime, PwsafeRecordField::PasswordModificationTime),
+            (RecordFieldType::LastModificationTime, PwsafeRecordField::LastModificationTime),
+        ] {
+            if !builder.has(field_type) {
+                builder = builder.field(field(now));
+            }
+        }
+        let mut record = PwsafeRecord::new(builder.fields);
+        record.sort_fields();
+        Ok(record)
+    }
+
+    fn has(&self, field_type: RecordFieldType) -> bool {
+        self.fields.iter().any(|f| f.field_type() == field_type.as_u8())
+    }
+}
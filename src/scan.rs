@@ -0,0 +1,97 @@
+use crate::field::{PwsafeHeaderField, PwsafeRecordField};
+use crate::reader::{Error, PwsafeReaderBuilder, Result};
+use std::io::Read;
+
+/// Outcome of `scan`.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    /// Database format version, `None` if the header has no valid version field
+    pub version: Option<u16>,
+    /// Number of records terminated by an end of record field
+    pub record_count: usize,
+    /// Whether the HMAC after the EOF block matches the fields read
+    pub hmac_valid: bool,
+    /// Field types parsed as `Blob`s, sorted and without duplicates
+    pub unknown_field_types: Vec<u8>,
+    /// Problems found while reading, in the order they were encountered
+    pub parse_errors: Vec<Error>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if the HMAC is valid and no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.hmac_valid && self.parse_errors.is_empty()
+    }
+
+    fn unknown_field_type(&mut self, field_type: u8) {
+        if let Err(i) = self.unknown_field_types.binary_search(&field_type) {
+            self.unknown_field_types.insert(i, field_type);
+        }
+    }
+}
+
+/// Reads a whole database and reports its integrity instead of failing on the first problem.
+///
+/// Only problems that prevent decrypting the database at all, like a wrong password or a
+/// truncated preamble, are returned as errors. Fields that fail to parse are recorded in
+/// `parse_errors` and reading goes on, a corrupted field stream is read in recovery mode, see
+/// `PwsafeReaderBuilder::recovery`.
+///
+/// ```rust
+/// use std::fs::File;
+///
+/// let file = File::open("tests/pwsafe.psafe3").unwrap();
+/// let report = pwsafer::scan(file, b"password").unwrap();
+/// assert!(report.is_ok());
+/// assert_eq!(report.record_count, 1);
+/// ```
+pub fn scan<R: Read>(inner: R, password: &[u8]) -> Result<IntegrityReport> {
+    let mut reader = PwsafeReaderBuilder::new().recovery(true).open(inner, password)?;
+    let mut report = IntegrityReport::default();
+    let mut in_header = true;
+    let mut in_record = false;
+    loop {
+        let (field_type, data) = match reader.read_field() {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                report.parse_errors.push(e);
+                break;
+            }
+        };
+        if in_header {
+            match PwsafeHeaderField::new(field_type, data) {
+                Ok(PwsafeHeaderField::Version(version)) if report.version.is_none() => {
+                    report.version = Some(version)
+                }
+                Ok(PwsafeHeaderField::EndOfHeader) => in_header = false,
+                Ok(PwsafeHeaderField::Blob { field_type, .. }) => report.unknown_field_type(field_type),
+                Ok(_) => (),
+                Err(e) => report.parse_errors.push(e.into()),
+            }
+        } else {
+            in_record = true;
+            match PwsafeRecordField::new(field_type, data) {
+                Ok(PwsafeRecordField::EndOfRecord) => {
+                    report.record_count += 1;
+                    in_record = false;
+                }
+                Ok(PwsafeRecordField::Blob { field_type, .. }) => report.unknown_field_type(field_type),
+                Ok(_) => (),
+                Err(e) => report.parse_errors.push(e.into()),
+            }
+        }
+    }
+    if in_header || report.version.is_none() {
+        report.parse_errors.push(Error::InvalidHeader);
+    }
+    if in_record {
+        report.parse_errors.push(Error::InvalidRecord);
+    }
+    match reader.verify() {
+        Ok(()) => report.hmac_valid = true,
+        Err(Error::MacError(_)) => (),
+        Err(e) => report.parse_errors.push(e),
+    }
+    Ok(report)
+}
@@ -0,0 +1,19 @@
+//! Zeroing of secrets. The only place that overwrites memory, keep it that way so there is
+//! one spot to audit.
+
+use std::sync::atomic;
+
+/// Overwrites `buf` with zeros in a way the compiler does not optimize away.
+pub fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // Safe, `byte` is a valid and aligned reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+/// Overwrites the contents of `s` with zeros and empties it.
+pub fn zeroize_string(s: &mut String) {
+    let mut bytes = std::mem::take(s).into_bytes();
+    zeroize(&mut bytes);
+}
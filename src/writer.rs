@@ -1,18 +1,141 @@
 use block_modes::block_padding::ZeroPadding;
 use block_modes::cipher::NewBlockCipher;
 use block_modes::{BlockMode, Cbc, Ecb};
-use byteorder::{LittleEndian, WriteBytesExt};
 use hmac::{Hmac, Mac, NewMac};
-use key::hash_password;
-use rand::{RngCore, rngs::OsRng};
-use sha2::{Digest, Sha256};
+use crate::field::{PwsafeHeaderField, PwsafeRecordField, RecordFieldType};
+use crate::header::revision_field;
+use crate::key::{key_hash, record_mac_key, stretch_key, KeyMaterial};
+use crate::reader::PwsafeReader;
+use crate::record::{random_uuid, rekey_record_mac, PwsafeRecord};
+use crate::EOF_SENTINEL;
+use rand::{CryptoRng, RngCore, rngs::OsRng};
+use sha2::Sha256;
 use std::cmp::min;
-use std::io::{self, Cursor, Write};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
 use std::result::Result;
-use twofish::{Twofish, cipher::generic_array::GenericArray};
+use std::thread;
+use twofish::Twofish;
+
+#[cfg(feature = "flate2")]
+use flate2::{write::GzEncoder, Compression};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncWrite;
 
 type TwofishCbc = Cbc<Twofish, ZeroPadding>;
 type HmacSha256 = Hmac<Sha256>;
+type FinishFn<W> = fn(&mut W) -> Result<(), io::Error>;
+
+/// Database format version written by `write_empty_header`.
+const VERSION: u16 = 0x030e;
+
+/// Type of the fields padding records, see `PwsafeWriter::set_record_padding`.
+pub(crate) const PADDING_FIELD_TYPE: u8 = 0xdf;
+
+/// Returns the header fields of a new database with the given UUID.
+pub(crate) fn empty_header(uuid: [u8; 16]) -> [PwsafeHeaderField; 3] {
+    [
+        PwsafeHeaderField::Version(VERSION),
+        PwsafeHeaderField::Uuid(uuid),
+        PwsafeHeaderField::EndOfHeader,
+    ]
+}
+
+/// Rewrites a database with a new iteration count, keeping the password.
+///
+/// Upgrades the key stretching of an old database, e.g. to a higher iteration count. All fields
+/// are copied unchanged, salt and keys are generated anew. On error the output is incomplete and
+/// must be discarded.
+///
+/// ```rust
+/// use std::fs::File;
+///
+/// let file = File::open("tests/pwsafe.psafe3").unwrap();
+/// let mut out = Vec::new();
+/// pwsafer::rekdf(file, b"password", &mut out, 100_000).unwrap();
+/// let db = pwsafer::PwsafeReader::new(&out[..], b"password").unwrap();
+/// assert_eq!(db.get_iter(), 100_000);
+/// ```
+pub fn rekdf<R: Read, W: Write>(inner: R, password: &[u8], out: W, iter: u32) -> Result<(), io::Error> {
+    rekey(inner, password, out, password, iter)
+}
+
+/// Rewrites many databases with a new password and iteration count, e.g. to rotate the master
+/// password of shared databases.
+///
+/// Each item is a source database and the writer for its new version. All fields are copied
+/// unchanged, the source HMAC is verified before the new database is finished. Failures do
+/// not stop the batch, the result for each database is returned in order. The output of a
+/// failed database is incomplete and must be discarded, write to temporary files and replace
+/// the originals only on success.
+///
+/// ```rust
+/// use std::fs;
+///
+/// let data = fs::read("tests/pwsafe.psafe3").unwrap();
+/// let (mut first, mut second) = (Vec::new(), Vec::new());
+/// let files = vec![(&data[..], &mut first), (&b"garbage"[..], &mut second)];
+/// let results = pwsafer::rotate_many(files, b"password", b"new password", 2048);
+/// assert!(results[0].is_ok());
+/// assert!(results[1].is_err());
+/// ```
+pub fn rotate_many<I, R, W>(files: I, old_password: &[u8], new_password: &[u8], iter: u32) -> Vec<Result<(), io::Error>>
+where
+    I: IntoIterator<Item = (R, W)>,
+    R: Read,
+    W: Write,
+{
+    files
+        .into_iter()
+        .map(|(inner, out)| rekey(inner, old_password, out, new_password, iter))
+        .collect()
+}
+
+/// Copies all fields of a database to a new one with the given password and iteration count.
+fn rekey<R: Read, W: Write>(inner: R, old_password: &[u8], out: W, new_password: &[u8], iter: u32) -> Result<(), io::Error> {
+    let mut reader = PwsafeReader::new(inner, old_password)?;
+    let mut writer = PwsafeWriter::new(out, iter, new_password)?;
+    writer.copy_fields(&mut reader)?;
+    writer.finish()
+}
+
+/// Returns the length prefix of a field, refusing lengths that do not fit its four bytes.
+fn check_field_len(len: usize) -> Result<u32, io::Error> {
+    u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field longer than 4 GiB"))
+}
+
+/// Generates keys of a new database protected by the stretched key.
+///
+/// Returns the unencrypted preamble and the plaintext keys.
+fn preamble<R: RngCore>(iter: u32, salt: &[u8; 32], key: &[u8; 32], rng: &mut R) -> (Vec<u8>, KeyMaterial) {
+    let mut preamble = b"PWS3".to_vec();
+    preamble.extend_from_slice(salt);
+    preamble.extend_from_slice(&iter.to_le_bytes());
+
+    preamble.extend_from_slice(&key_hash(key));
+
+    let mut k = [0u8; 32];
+    let mut l = [0u8; 32];
+    let mut iv = [0u8; 16];
+    rng.fill_bytes(&mut k);
+    rng.fill_bytes(&mut l);
+    rng.fill_bytes(&mut iv);
+
+    let mut k_ = k;
+    let mut l_ = l;
+
+    let twofish_cipher = Twofish::new_from_slice(key).unwrap();
+    let mut ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &Default::default());
+    ecb_cipher.encrypt(&mut k_, k.len()).unwrap();
+    ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &Default::default());
+    ecb_cipher.encrypt(&mut l_, l.len()).unwrap();
+
+    preamble.extend_from_slice(&k_);
+    preamble.extend_from_slice(&l_);
+    preamble.extend_from_slice(&iv);
+
+    (preamble, KeyMaterial { k, l, iv })
+}
 
 /// Password safe writer.
 ///
@@ -20,122 +143,576 @@ type HmacSha256 = Hmac<Sha256>;
 ///
 /// An example shows how to create an empty database.
 /// ```no_run
-/// use pwsafe::PwsafeWriter;
+/// use pwsafer::PwsafeWriter;
 /// use std::fs::File;
 /// use std::io::BufWriter;
 ///
 /// let filename = "pwsafe.psafe3";
 /// let file = BufWriter::new(File::create(filename).unwrap());
 /// let mut db = PwsafeWriter::new(file, 2048, b"password").unwrap();
-/// let version = [0x0eu8, 0x03u8];
-/// let empty = [0u8, 0];
-/// db.write_field(0x00, &version).unwrap(); // Version field
-/// db.write_field(0xff, &empty).unwrap(); // End of header
+/// let uuid = db.write_empty_header().unwrap(); // Version, UUID and end of header
+/// println!("Created database {:x?}", uuid);
 /// db.finish().unwrap(); // EOF and HMAC
 /// ```
-pub struct PwsafeWriter<W> {
+///
+/// Each field is written to the underlying writer as soon as it is encrypted and errors are
+/// returned right away. After a failed write the underlying writer holds an unknown prefix of the
+/// database which must be discarded, all further calls fail.
+///
+/// The writer is `Send` and `Sync` whenever the underlying writer and random number generator
+/// are.
+///
+/// Everything after the preamble is ciphertext, so compressing a database hardly shrinks it.
+/// For transport formats that expect compression, see `new_gz` with the `flate2` feature.
+pub struct PwsafeWriter<W, R = OsRng> {
     inner: W,
-    buffer: Vec<u8>,
+    rng: R,
     k: [u8; 32],
+    /// CBC chaining value, the last encrypted block
     iv: [u8; 16],
     hmac: HmacSha256,
+    eof_sentinel: [u8; 16],
+    finished: bool,
+    /// Whether writing to the underlying writer failed
+    failed: bool,
+    /// Whether `write_record_field` started a record that was not ended yet
+    in_record: bool,
+    /// Application recorded by `write_empty_header`, see `set_last_save_app`
+    last_save_app: Option<String>,
+    /// Revision counter recorded by `write_empty_header`, see `set_revision`
+    revision: Option<u64>,
+    /// Key of record MACs, see `record_mac_key`
+    record_mac_key: [u8; 32],
+    /// Whether `write_record` adds record MACs, see `set_record_macs`
+    record_macs: bool,
+    /// Blocks every record is padded to, see `set_record_padding`
+    record_padding: Option<usize>,
+    /// Blocks written since the last end of header or end of record marker
+    record_blocks: usize,
+    /// Ends the stream of the underlying writer on `finish` instead of flushing it
+    finish_inner: Option<FinishFn<W>>,
+    #[cfg(feature = "test-vectors")]
+    keys: KeyMaterial,
+    #[cfg(feature = "debug-hmac")]
+    hmac_snapshots: Vec<[u8; 32]>,
 }
 
 impl<W: Write> PwsafeWriter<W> {
     /// Creates a new `PwsafeWriter` with the given password.
-    pub fn new(mut inner: W, iter: u32, password: &[u8]) -> Result<Self, io::Error> {
-        inner.write_all(b"PWS3")?;
+    ///
+    /// An empty password is allowed, the database is then protected by nothing but the key
+    /// stretching of an empty string and anyone can open it. Password Safe opens such databases
+    /// too. Applications should refuse empty master passwords when asking for a new one.
+    pub fn new(inner: W, iter: u32, password: &[u8]) -> Result<Self, io::Error> {
+        PwsafeWriter::with_rng(inner, iter, password, OsRng)
+    }
+
+    /// Creates a new `PwsafeWriter` with the given password and salt.
+    ///
+    /// The preamble up to the password hash then only depends on the inputs, which allows to
+    /// compare it after re-keying. Keys and padding stay random, use `with_salt_and_rng` for
+    /// entirely reproducible output.
+    pub fn with_salt(inner: W, iter: u32, password: &[u8], salt: [u8; 32]) -> Result<Self, io::Error> {
+        PwsafeWriter::with_salt_and_rng(inner, iter, password, salt, OsRng)
+    }
+
+    /// Creates a new `PwsafeWriter` with a key stretched by `stretch_key` instead of the
+    /// password, skipping the slow key stretching.
+    ///
+    /// The salt and iteration count must be the ones the key was derived with. Reusing them
+    /// for a new version of a database is fine, all other keys are generated anew.
+    pub fn from_key(mut inner: W, iter: u32, salt: &[u8; 32], key: &[u8; 32]) -> Result<Self, io::Error> {
+        let (preamble, keys) = preamble(iter, salt, key, &mut OsRng);
+        inner.write_all(&preamble)?;
+        Ok(PwsafeWriter::from_parts(inner, OsRng, keys))
+    }
+}
 
+impl<W: Write, R: CryptoRng + RngCore> PwsafeWriter<W, R> {
+    /// Creates a new `PwsafeWriter` with the given password taking salt, keys and padding from
+    /// the given random number generator.
+    ///
+    /// A seeded generator makes the output reproducible, which is useful for tests. Databases
+    /// meant to protect anything must use `new`.
+    pub fn with_rng(inner: W, iter: u32, password: &[u8], mut rng: R) -> Result<Self, io::Error> {
         let mut salt = [0u8; 32];
-        OsRng.fill_bytes(&mut salt);
-        inner.write_all(&salt)?;
-        inner.write_u32::<LittleEndian>(iter)?;
+        rng.fill_bytes(&mut salt);
+        PwsafeWriter::with_salt_and_rng(inner, iter, password, salt, rng)
+    }
+
+    /// Creates a new `PwsafeWriter` with the given password and salt taking keys and padding
+    /// from the given random number generator.
+    ///
+    /// Combines `with_salt` and `with_rng`, with a seeded generator the whole output only
+    /// depends on the inputs. Databases meant to protect anything must use `new`.
+    pub fn with_salt_and_rng(mut inner: W, iter: u32, password: &[u8], salt: [u8; 32], mut rng: R) -> Result<Self, io::Error> {
+        let key = stretch_key(&salt, iter, password);
+        let (preamble, keys) = preamble(iter, &salt, &key, &mut rng);
+        inner.write_all(&preamble)?;
+        Ok(PwsafeWriter::from_parts(inner, rng, keys))
+    }
+
+    /// Encrypts and writes one field.
+    pub fn write_field(&mut self, field_type: u8, data: &[u8]) -> Result<(), io::Error> {
+        self.check_unfinished()?;
+        let blocks = self.encrypt_field(field_type, data)?;
+        let res = self.inner.write_all(&blocks);
+        self.track(res)
+    }
+
+    /// Writes a field of a type this crate does not know, e.g. one read as `Blob`.
+    ///
+    /// Same as `write_field`, the type byte and contents are written unchanged so fields added
+    /// by newer applications survive a rewrite.
+    pub fn write_blob(&mut self, field_type: u8, data: &[u8]) -> Result<(), io::Error> {
+        self.write_field(field_type, data)
+    }
+
+    /// Copies all remaining fields of `reader` unchanged and verifies its HMAC.
+    ///
+    /// Record MACs that verify under the key of `reader` are replaced with ones under the key
+    /// of this writer, see `PwsafeRecord::set_record_mac`. Invalid ones are copied as they are.
+    /// On error, including a read error or HMAC mismatch of `reader`, the output is incomplete
+    /// and must be discarded, all further calls fail.
+    pub fn copy_fields<T>(&mut self, reader: &mut PwsafeReader<T>) -> Result<(), io::Error> {
+        let res = self.copy_fields_from(reader);
+        self.track(res)
+    }
+
+    fn copy_fields_from<T>(&mut self, reader: &mut PwsafeReader<T>) -> Result<(), io::Error> {
+        let old_key = reader.record_mac_key();
+        let mut record = Vec::new();
+        loop {
+            let in_header = reader.in_header();
+            let (field_type, data) = match reader.read_field()? {
+                Some(field) => field,
+                None => break,
+            };
+            if in_header {
+                self.write_field(field_type, &data)?;
+                continue;
+            }
+            record.push((field_type, data));
+            if field_type == RecordFieldType::EndOfRecord.as_u8() {
+                rekey_record_mac(&mut record, &old_key, &self.record_mac_key);
+                for (field_type, data) in record.drain(..) {
+                    self.write_field(field_type, &data)?;
+                }
+            }
+        }
+        // Fields of a record without end of record marker
+        for (field_type, data) in record {
+            self.write_field(field_type, &data)?;
+        }
+        Ok(reader.verify()?)
+    }
+
+    /// Writes one header field, a `Blob` with its stored type byte.
+    ///
+    /// The end of header marker must be written explicitly, as `PwsafeHeaderField::EndOfHeader`.
+    pub fn write_header_field(&mut self, field: &PwsafeHeaderField) -> Result<(), io::Error> {
+        let (field_type, data) = field.to_field();
+        self.write_field(field_type, &data)
+    }
+
+    /// Writes the minimal header of a new database.
+    ///
+    /// Writes the version field, a freshly generated database UUID, the application set with
+    /// `set_last_save_app` and the revision set with `set_revision` if any and the end of header
+    /// marker. Records may be written right after. Returns the generated UUID.
+    pub fn write_empty_header(&mut self) -> Result<[u8; 16], io::Error> {
+        let uuid = self.random_uuid();
+        for field in &self.empty_header(uuid) {
+            let (field_type, data) = field.to_field();
+            self.write_field(field_type, &data)?;
+        }
+        Ok(uuid)
+    }
+
+    /// Writes the fields of a record in canonical order followed by the end of record marker.
+    ///
+    /// See `PwsafeRecord::sort_fields`.
+    pub fn write_record(&mut self, record: &PwsafeRecord) -> Result<(), io::Error> {
+        let mut record = record.clone();
+        record.sort_fields();
+        if self.record_macs {
+            record.set_record_mac(&self.record_mac_key);
+        }
+        for field in record.fields() {
+            let (field_type, data) = field.to_field();
+            self.write_field(field_type, &data)?;
+        }
+        self.end_record()
+    }
+
+    /// Writes one record field, a `Blob` with its stored type byte.
+    ///
+    /// Keeps track of the records: a `Uuid` field following other fields written by this method
+    /// starts a new record, the end of record marker of the previous one is inserted
+    /// automatically. The same happens for the last record on `finish`. Use `end_record` to end
+    /// a record explicitly, e.g. one without UUID.
+    pub fn write_record_field(&mut self, field: &PwsafeRecordField) -> Result<(), io::Error> {
+        match field {
+            PwsafeRecordField::EndOfRecord => return self.end_record(),
+            PwsafeRecordField::Uuid(_) if self.in_record => self.end_record()?,
+            _ => (),
+        }
+        let (field_type, data) = field.to_field();
+        self.write_field(field_type, &data)?;
+        self.in_record = true;
+        Ok(())
+    }
+
+    /// Writes the end of record marker, preceded by padding if enabled.
+    pub fn end_record(&mut self) -> Result<(), io::Error> {
+        if let Some(padding) = self.record_padding_data() {
+            self.write_field(PADDING_FIELD_TYPE, &padding)?;
+        }
+        self.write_field(RecordFieldType::EndOfRecord.as_u8(), &[])?;
+        self.in_record = false;
+        Ok(())
+    }
+
+    /// Writes EOF block and HMAC and flushes the writer.
+    ///
+    /// Must be called exactly once after the last field, the database is incomplete without it.
+    /// Fields can not be written afterwards. Ends a record started by `write_record_field`.
+    pub fn finish(&mut self) -> Result<(), io::Error> {
+        self.check_unfinished()?;
+        if self.in_record {
+            self.end_record()?;
+        }
+        self.finished = true;
+        let trailer = self.trailer();
+        let finish_inner = self.finish_inner.unwrap_or(W::flush);
+        let res = self.inner.write_all(&trailer).and_then(|_| finish_inner(&mut self.inner));
+        self.track(res)
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl<W: Write> PwsafeWriter<GzEncoder<W>> {
+    /// Creates a new `PwsafeWriter` with the given password writing a gzip compressed database.
+    ///
+    /// `finish` completes the gzip stream. Everything after the preamble is ciphertext, so the
+    /// output is hardly smaller than an uncompressed database. Only use it for transport or
+    /// backup formats that expect compression. Read it with `PwsafeReader::new_gz`.
+    pub fn new_gz(inner: W, iter: u32, password: &[u8]) -> Result<Self, io::Error> {
+        let mut writer = PwsafeWriter::new(GzEncoder::new(inner, Compression::default()), iter, password)?;
+        writer.finish_inner = Some(GzEncoder::try_finish);
+        Ok(writer)
+    }
+}
 
-        let key = hash_password(&salt, iter, password);
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> PwsafeWriter<W> {
+    /// Creates a new `PwsafeWriter` with the given password writing to an asynchronous writer.
+    ///
+    /// Key stretching runs on the current task, so with high iteration counts consider calling
+    /// this from `spawn_blocking`.
+    pub async fn new_async(mut inner: W, iter: u32, password: &[u8]) -> Result<Self, io::Error> {
+        use tokio::io::AsyncWriteExt;
 
-        let mut hasher = Sha256::default();
-        hasher.update(&key);
-        let hash = hasher.finalize();
-        inner.write_all(&hash)?;
+        let mut salt = [0u8; 32];
+        OsRng.fill_bytes(&mut salt);
+        let key = stretch_key(&salt, iter, password);
+        let (preamble, keys) = preamble(iter, &salt, &key, &mut OsRng);
+        inner.write_all(&preamble).await?;
+        Ok(PwsafeWriter::from_parts(inner, OsRng, keys))
+    }
+}
 
-        let mut k = [0u8; 32];
-        let mut l = [0u8; 32];
-        let mut iv = [0u8; 16];
-        OsRng.fill_bytes(&mut k);
-        OsRng.fill_bytes(&mut l);
-        OsRng.fill_bytes(&mut iv);
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin, R: CryptoRng + RngCore> PwsafeWriter<W, R> {
+    /// Encrypts and writes one field to an asynchronous writer.
+    ///
+    /// Encryption runs synchronously, only the write is asynchronous.
+    pub async fn write_field_async(&mut self, field_type: u8, data: &[u8]) -> Result<(), io::Error> {
+        use tokio::io::AsyncWriteExt;
 
-        let mut k_ = k.clone();
-        let mut l_ = l.clone();
-        let iv_ = iv.clone();
+        self.check_unfinished()?;
+        let blocks = self.encrypt_field(field_type, data)?;
+        let res = self.inner.write_all(&blocks).await;
+        self.track(res)
+    }
 
-        let sha256_hmac = HmacSha256::new_from_slice(&l).unwrap();
+    /// Writes the minimal header of a new database to an asynchronous writer.
+    ///
+    /// See `write_empty_header`.
+    pub async fn write_empty_header_async(&mut self) -> Result<[u8; 16], io::Error> {
+        let uuid = self.random_uuid();
+        for field in &self.empty_header(uuid) {
+            let (field_type, data) = field.to_field();
+            self.write_field_async(field_type, &data).await?;
+        }
+        Ok(uuid)
+    }
 
-        let twofish_cipher = Twofish::new_from_slice(&key).unwrap();
-        let mut ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &GenericArray::default());
-        ecb_cipher.encrypt(&mut k_, k.len()).unwrap();
-        ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &GenericArray::default());
-        ecb_cipher.encrypt(&mut l_, l.len()).unwrap();
+    /// Writes one record field to an asynchronous writer.
+    ///
+    /// See `write_record_field`.
+    pub async fn write_record_field_async(&mut self, field: &PwsafeRecordField) -> Result<(), io::Error> {
+        match field {
+            PwsafeRecordField::EndOfRecord => return self.end_record_async().await,
+            PwsafeRecordField::Uuid(_) if self.in_record => self.end_record_async().await?,
+            _ => (),
+        }
+        let (field_type, data) = field.to_field();
+        self.write_field_async(field_type, &data).await?;
+        self.in_record = true;
+        Ok(())
+    }
 
-        inner.write_all(&k_)?;
-        inner.write_all(&l_)?;
-        inner.write_all(&iv_)?;
+    /// Writes the end of record marker to an asynchronous writer.
+    ///
+    /// See `end_record`.
+    pub async fn end_record_async(&mut self) -> Result<(), io::Error> {
+        if let Some(padding) = self.record_padding_data() {
+            self.write_field_async(PADDING_FIELD_TYPE, &padding).await?;
+        }
+        self.write_field_async(RecordFieldType::EndOfRecord.as_u8(), &[]).await?;
+        self.in_record = false;
+        Ok(())
+    }
 
-        let buffer = Vec::new();
+    /// Writes EOF block and HMAC and flushes the writer.
+    ///
+    /// See `finish`.
+    pub async fn finish_async(&mut self) -> Result<(), io::Error> {
+        use tokio::io::AsyncWriteExt;
 
-        let w = PwsafeWriter {
+        self.check_unfinished()?;
+        if self.in_record {
+            self.end_record_async().await?;
+        }
+        self.finished = true;
+        let trailer = self.trailer();
+        let mut res = self.inner.write_all(&trailer).await;
+        if res.is_ok() {
+            res = self.inner.flush().await;
+        }
+        self.track(res)
+    }
+}
+
+impl<W, R: CryptoRng + RngCore> PwsafeWriter<W, R> {
+    fn from_parts(inner: W, rng: R, keys: KeyMaterial) -> Self {
+        let hmac = HmacSha256::new_from_slice(&keys.l).unwrap();
+        PwsafeWriter {
             inner,
-            buffer,
-            k,
-            iv,
-            hmac: sha256_hmac,
-        };
-        Ok(w)
+            rng,
+            k: keys.k,
+            iv: keys.iv,
+            record_mac_key: record_mac_key(&hmac),
+            hmac,
+            eof_sentinel: *EOF_SENTINEL,
+            finished: false,
+            failed: false,
+            in_record: false,
+            last_save_app: None,
+            revision: None,
+            record_macs: false,
+            record_padding: None,
+            record_blocks: 0,
+            finish_inner: None,
+            #[cfg(feature = "test-vectors")]
+            keys,
+            #[cfg(feature = "debug-hmac")]
+            hmac_snapshots: Vec::new(),
+        }
     }
 
-    /// Prepares one field.
-    pub fn write_field(&mut self, field_type: u8, data: &[u8]) -> Result<(), io::Error> {
-        let mut i: usize = 0;
-        let mut block = [0u8; 16];
-        let mut cur = Cursor::new(Vec::new());
-        cur.write_u32::<LittleEndian>(data.len() as u32)?;
-        cur.write_u8(field_type)?;
+    /// Returns the plaintext subkeys and initial IV.
+    ///
+    /// Leaks key material, only meant for conformance tests.
+    #[cfg(feature = "test-vectors")]
+    pub fn key_material(&self) -> &KeyMaterial {
+        &self.keys
+    }
 
-        self.hmac.update(&data);
-        loop {
-            let l = min(16 - cur.get_ref().len(), data.len() - i);
-            cur.write_all(&data[i..i + l])?;
+    /// Returns the running HMAC after each field written so far.
+    ///
+    /// See `PwsafeReader::hmac_snapshots`.
+    #[cfg(feature = "debug-hmac")]
+    pub fn hmac_snapshots(&self) -> &[[u8; 32]] {
+        &self.hmac_snapshots
+    }
 
-            if l == 0 {
-                i += 16
-            } else {
-                i += l;
-            }
+    /// Pads every record written afterwards to at least `blocks` encrypted blocks, `None`
+    /// disables padding, which is the default.
+    ///
+    /// Without padding the number of blocks of a record leaks the approximate length of its
+    /// fields. With padding records no larger than `blocks` all have the same size, larger ones
+    /// are left as they are. The padding is an extra field of type 0xdf stored before the end
+    /// of record marker, which Password Safe and this crate keep as an unknown field. The file
+    /// grows accordingly and applications that rewrite the database keep the padding fields,
+    /// even when the record shrinks or grows later.
+    ///
+    /// A field takes one block for up to 11 bytes and another one for every further 16 bytes. A
+    /// record of a UUID, a short title and a short password takes five blocks including the end
+    /// of record marker.
+    pub fn set_record_padding(&mut self, blocks: Option<usize>) {
+        self.record_padding = blocks;
+    }
+
+    /// Sets the application `write_empty_header` records as `LastSaveWhat`, e.g. `APP_NAME`.
+    ///
+    /// None by default. Headers written field by field are written as they are, set the
+    /// application there with `PwsafeHeader::set_last_save_app`.
+    pub fn set_last_save_app(&mut self, app: Option<String>) {
+        self.last_save_app = app;
+    }
+
+    /// Makes `write_record` add a record MAC to every record, see
+    /// `PwsafeRecord::set_record_mac`.
+    ///
+    /// Off by default, the MACs are a nonstandard extension only this crate checks. Records
+    /// written field by field are left alone, add their MACs with `record_mac_key`.
+    pub fn set_record_macs(&mut self, enabled: bool) {
+        self.record_macs = enabled;
+    }
+
+    /// Returns the key of record MACs of this database, see `PwsafeRecord::set_record_mac`.
+    pub fn record_mac_key(&self) -> [u8; 32] {
+        self.record_mac_key
+    }
+
+    /// Sets the revision counter `write_empty_header` records, see
+    /// `PwsafeHeader::set_revision`.
+    ///
+    /// None by default. To bump the counter of an existing database, read its header, call
+    /// `PwsafeHeader::bump_revision` and write the header field by field.
+    pub fn set_revision(&mut self, revision: Option<u64>) {
+        self.revision = revision;
+    }
+
+    /// Sets the unencrypted block written after the encrypted fields.
+    ///
+    /// Defaults to `EOF_SENTINEL`. Databases with other values can not be read by other
+    /// applications.
+    pub fn set_eof_sentinel(&mut self, sentinel: [u8; 16]) {
+        self.eof_sentinel = sentinel;
+    }
+
+    /// Discards the database without finishing it, e.g. after an error of the caller.
+    ///
+    /// The underlying writer holds an incomplete database which must be discarded. Dropping an
+    /// unfinished writer has the same effect but prints a warning in debug builds.
+    pub fn abandon(mut self) {
+        self.failed = true;
+    }
+
+    fn check_unfinished(&self) -> Result<(), io::Error> {
+        if self.failed {
+            return Err(io::Error::other("previous write failed, the database is incomplete"));
+        }
+        if self.finished {
+            return Err(io::Error::other("database already finished"));
+        }
+        Ok(())
+    }
 
-            let v = cur.into_inner();
-            let vlen = v.len();
-            block[0..vlen].copy_from_slice(&v);
-            OsRng.fill_bytes(&mut block[vlen..16]); // Pad with random bytes
+    /// Remembers a failed write, the CBC chain and HMAC already include the lost data.
+    fn track(&mut self, res: Result<(), io::Error>) -> Result<(), io::Error> {
+        if res.is_err() {
+            self.failed = true;
+        }
+        res
+    }
 
-            self.buffer.append(&mut block.to_vec());
+    /// Encodes one field into blocks and encrypts them, continuing the CBC chain.
+    fn encrypt_field(&mut self, field_type: u8, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let len = check_field_len(data.len())?;
+        // Five bytes of length and type are followed by the data, padded to whole blocks
+        let blocks = 1 + data.len().saturating_sub(11).div_ceil(16);
+        let mut buffer = Vec::with_capacity(blocks * 16);
+        let mut block = [0u8; 16];
+        block[..4].copy_from_slice(&len.to_le_bytes());
+        block[4] = field_type;
+        let mut offset = 5;
+        let mut i: usize = 0;
 
-            cur = Cursor::new(Vec::new());
+        self.hmac.update(data);
+        #[cfg(feature = "debug-hmac")]
+        self.hmac_snapshots.push(self.hmac.clone().finalize().into_bytes().into());
+        loop {
+            let l = min(16 - offset, data.len() - i);
+            block[offset..offset + l].copy_from_slice(&data[i..i + l]);
+            i += l;
+            self.rng.fill_bytes(&mut block[offset + l..]); // Pad with random bytes
+            buffer.extend_from_slice(&block);
+            offset = 0;
             if i >= data.len() {
                 break;
             }
         }
-        Ok(())
-    }
 
-    /// Encrypts/Writes all fields, EOF block and HMAC.
-    pub fn finish(&mut self) -> Result<(), io::Error> {
-        let mut fields = self.buffer.clone();
-        let pos = self.buffer.len();
+        let pos = buffer.len();
         let cbc_cipher = TwofishCbc::new_from_slices(&self.k, &self.iv).unwrap();
-        cbc_cipher.encrypt(&mut fields, pos).unwrap();
-        self.inner.write_all(&fields)?;
-        self.inner.write_all(b"PWS3-EOFPWS3-EOF")?;
-        self.inner.write_all(&self.hmac.clone().finalize().into_bytes())?;
-        Ok(())
+        cbc_cipher.encrypt(&mut buffer, pos).unwrap();
+        self.iv.copy_from_slice(&buffer[pos - 16..]);
+        if field_type == RecordFieldType::EndOfRecord.as_u8() {
+            self.record_blocks = 0;
+        } else {
+            self.record_blocks += pos / 16;
+        }
+        Ok(buffer)
+    }
+
+    /// Returns the contents of the padding field the current record needs before its end of
+    /// record marker, if any.
+    fn record_padding_data(&self) -> Option<Vec<u8>> {
+        // The end of record marker takes one block
+        let missing = self.record_padding?.saturating_sub(self.record_blocks + 1);
+        // One block holds 11 bytes of the first field, the others 16 bytes each
+        (missing > 0).then(|| vec![0; 16 * (missing - 1)])
+    }
+
+    /// Returns the fields written by `write_empty_header`.
+    fn empty_header(&self, uuid: [u8; 16]) -> Vec<PwsafeHeaderField> {
+        let mut fields = empty_header(uuid).to_vec();
+        if let Some(app) = &self.last_save_app {
+            fields.insert(fields.len() - 1, PwsafeHeaderField::LastSaveWhat(app.clone()));
+        }
+        if let Some(revision) = self.revision {
+            fields.insert(fields.len() - 1, revision_field(revision));
+        }
+        fields
+    }
+
+    /// Returns a random (version 4) UUID.
+    fn random_uuid(&mut self) -> [u8; 16] {
+        random_uuid(&mut self.rng)
+    }
+
+    /// Returns EOF block followed by HMAC.
+    fn trailer(&self) -> Vec<u8> {
+        let mut trailer = self.eof_sentinel.to_vec();
+        trailer.extend_from_slice(&self.hmac.clone().finalize().into_bytes());
+        trailer
+    }
+}
+
+impl<W, R> Drop for PwsafeWriter<W, R> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !self.finished && !self.failed && !thread::panicking() {
+            eprintln!("warning: PwsafeWriter dropped without calling finish, the database is incomplete");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_field_len;
+
+    #[test]
+    fn field_len() {
+        assert_eq!(check_field_len(0).unwrap(), 0);
+        assert_eq!(check_field_len(u32::MAX as usize).unwrap(), u32::MAX);
+        #[cfg(target_pointer_width = "64")]
+        {
+            let err = check_field_len(u32::MAX as usize + 1).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
     }
 }
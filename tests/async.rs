@@ -0,0 +1,60 @@
+#![cfg(feature = "tokio")]
+
+extern crate pwsafer;
+
+use pwsafer::{PwsafeDatabase, PwsafeReader, PwsafeRecordField, PwsafeWriter, ReaderError};
+use std::fs;
+
+#[tokio::test]
+async fn read_async() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut db = PwsafeReader::new_async(&data[..], b"password").await.unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030d);
+    while db.read_field().unwrap().is_some() {}
+    db.verify().unwrap();
+
+    match PwsafeReader::new_async(&data[..], b"wrong").await {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[tokio::test]
+async fn write_async() {
+    let mut buf = Vec::new();
+    let uuid = {
+        let mut db = PwsafeWriter::new_async(&mut buf, 2048, b"password").await.unwrap();
+        let uuid = db.write_empty_header_async().await.unwrap();
+        db.write_field_async(0x03, b"title").await.unwrap();
+        db.write_field_async(0xff, &[]).await.unwrap();
+        db.finish_async().await.unwrap();
+        uuid
+    };
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030e);
+    assert_eq!(db.read_field().unwrap().unwrap(), (0x01, uuid.to_vec()));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0xff, vec![]));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0x03, b"title".to_vec()));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0xff, vec![]));
+    assert!(db.read_field().unwrap().is_none());
+    db.verify().unwrap();
+}
+
+#[tokio::test]
+async fn finish_async_ends_record() {
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new_async(&mut buf, 2048, b"password").await.unwrap();
+        db.write_empty_header_async().await.unwrap();
+        for (uuid, title) in &[([1; 16], "one"), ([2; 16], "two")] {
+            db.write_record_field_async(&PwsafeRecordField::Uuid(*uuid)).await.unwrap();
+            db.write_record_field_async(&PwsafeRecordField::Title(title.to_string())).await.unwrap();
+        }
+        db.finish_async().await.unwrap();
+    }
+
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    let titles: Vec<_> = db.records().iter().map(|r| r.title()).collect();
+    assert_eq!(titles, vec![Some("one"), Some("two")]);
+}
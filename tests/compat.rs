@@ -0,0 +1,138 @@
+extern crate pwsafer;
+
+use pwsafer::{NamedPasswordPolicy, PasswordPolicy, PwsafeHeaderField, PwsafeReader, PwsafeRecordField};
+use std::fs::File;
+use std::io::BufReader;
+
+fn read(filename: &str) -> (Vec<PwsafeHeaderField>, Vec<PwsafeRecordField>) {
+    let file = BufReader::new(File::open(filename).unwrap());
+    let mut db = PwsafeReader::new(file, b"password").unwrap();
+    let header = db.typed_header_fields().map(|f| f.unwrap()).collect();
+    let fields = db.typed_fields().map(|f| f.unwrap()).collect();
+    db.verify().unwrap();
+    (header, fields)
+}
+
+/// Database saved by Password Safe.
+#[test]
+fn pwsafe_fixture() {
+    let (header, fields) = read("tests/pwsafe.psafe3");
+    assert_eq!(
+        header,
+        vec![
+            PwsafeHeaderField::Version(0x030d),
+            PwsafeHeaderField::Uuid([
+                0x83, 0xf8, 0xd9, 0x49, 0xdc, 0xba, 0x48, 0xad, 0xb4, 0xec, 0xf2, 0x3d, 0xf9, 0x0f, 0x04, 0xae
+            ]),
+            PwsafeHeaderField::Preferences(String::new()),
+            PwsafeHeaderField::LastSaveTimestamp(1632081688),
+            PwsafeHeaderField::LastSaveUser("gabriel".to_string()),
+            PwsafeHeaderField::LastSaveHost("Jeff".to_string()),
+            PwsafeHeaderField::LastSaveWhat("pwsafe V1.04".to_string()),
+        ]
+    );
+    assert_eq!(
+        fields,
+        vec![
+            PwsafeRecordField::Uuid([
+                0x12, 0x09, 0xa0, 0xac, 0x5c, 0xd0, 0x4a, 0xfc, 0x98, 0xf7, 0xdf, 0xec, 0x6e, 0x16, 0x50, 0x42
+            ]),
+            PwsafeRecordField::Title("test".to_string()),
+            PwsafeRecordField::Username("test".to_string()),
+            PwsafeRecordField::Password("test".to_string()),
+            PwsafeRecordField::CreationTime(1632081681),
+            PwsafeRecordField::PasswordExpiryInterval(90),
+            PwsafeRecordField::EndOfRecord,
+        ]
+    );
+}
+
+/// Database with credit card and password policy fields.
+#[test]
+fn fields_fixture() {
+    let (header, fields) = read("tests/fields.psafe3");
+    assert_eq!(header[0], PwsafeHeaderField::Version(0x030e));
+    assert!(header.contains(&PwsafeHeaderField::DatabaseName("Fields".to_string())));
+    let policies = header
+        .iter()
+        .find_map(|f| match f {
+            PwsafeHeaderField::NamedPasswordPolicies(s) => Some(NamedPasswordPolicy::parse_list(s).unwrap()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(policies.len(), 1);
+    assert_eq!(policies[0].name, "Digits");
+    assert!(policies[0].policy.has_flag(PasswordPolicy::USE_DIGITS));
+
+    for field in &[
+        PwsafeRecordField::Group("Finance.Cards".to_string()),
+        PwsafeRecordField::Title("Visa".to_string()),
+        PwsafeRecordField::EmailAddress("alice@example.com".to_string()),
+        PwsafeRecordField::PasswordPolicyName("Digits".to_string()),
+        PwsafeRecordField::CreditCardNumber("4111111111111111".to_string()),
+        PwsafeRecordField::CreditCardExpiration("12/30".to_string()),
+        PwsafeRecordField::CreditCardVerifValue("123".to_string()),
+        PwsafeRecordField::CreditCardPin("4321".to_string()),
+    ] {
+        assert!(fields.contains(field), "{:?}", field);
+    }
+    let policy = fields
+        .iter()
+        .find_map(|f| match f {
+            PwsafeRecordField::PasswordPolicy(s) => Some(PasswordPolicy::parse(s).unwrap()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(policy.length, 12);
+    assert_eq!(fields.last(), Some(&PwsafeRecordField::EndOfRecord));
+}
+
+/// Integer fields are little-endian and UUIDs keep their stored byte order.
+#[test]
+fn byte_order() {
+    for filename in &["tests/pwsafe.psafe3", "tests/fields.psafe3"] {
+        let file = BufReader::new(File::open(filename).unwrap());
+        let mut db = PwsafeReader::new(file, b"password").unwrap();
+        let mut in_header = true;
+        let mut checked = 0;
+        while let Some((field_type, data)) = db.read_field().unwrap() {
+            let le = |len: usize| {
+                assert_eq!(data.len(), len);
+                data.iter().rev().fold(0u32, |acc, &b| (acc << 8) | u32::from(b))
+            };
+            if in_header {
+                in_header = field_type != 0xff;
+                match PwsafeHeaderField::new(field_type, data.clone()).unwrap() {
+                    PwsafeHeaderField::Version(v) => assert_eq!(u32::from(v), le(2)),
+                    PwsafeHeaderField::Uuid(uuid) => assert_eq!(&uuid[..], &data[..]),
+                    PwsafeHeaderField::LastSaveTimestamp(t)
+                    | PwsafeHeaderField::LastMasterPasswordChange(t) => assert_eq!(t, le(4)),
+                    _ => continue,
+                }
+            } else {
+                match PwsafeRecordField::new(field_type, data.clone()).unwrap() {
+                    PwsafeRecordField::Uuid(uuid) => assert_eq!(&uuid[..], &data[..]),
+                    PwsafeRecordField::CreationTime(i)
+                    | PwsafeRecordField::PasswordModificationTime(i)
+                    | PwsafeRecordField::LastAccessTime(i)
+                    | PwsafeRecordField::PasswordExpiryTime(i)
+                    | PwsafeRecordField::LastModificationTime(i)
+                    | PwsafeRecordField::PasswordExpiryInterval(i)
+                    | PwsafeRecordField::EntryKeyboardShortcut(i)
+                    | PwsafeRecordField::TotpStartTime(i) => assert_eq!(i, le(4)),
+                    PwsafeRecordField::DoubleClickAction(i)
+                    | PwsafeRecordField::ShiftDoubleClickAction(i) => assert_eq!(u32::from(i), le(2)),
+                    PwsafeRecordField::ProtectedEntry(i) => assert_eq!(u32::from(i), le(1)),
+                    _ => continue,
+                }
+            }
+            checked += 1;
+        }
+        db.verify().unwrap();
+        assert!(checked >= 4, "{}", filename);
+    }
+
+    // Key code 'A' in the low 16 bits, modifier flags above
+    let field = PwsafeRecordField::new(0x19, vec![0x41, 0x00, 0x03, 0x00]).unwrap();
+    assert_eq!(field, PwsafeRecordField::EntryKeyboardShortcut(0x0003_0041));
+}
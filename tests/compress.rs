@@ -0,0 +1,42 @@
+#![cfg(feature = "compress")]
+
+extern crate pwsafer;
+
+use pwsafer::{PwsafeReader, PwsafeRecordField, PwsafeWriter};
+
+#[test]
+fn compressed_notes() {
+    let long = "line of notes\n".repeat(200);
+    let field = PwsafeRecordField::Notes(long.clone());
+    let (field_type, data) = field.to_field();
+    assert!(data.starts_with(b"\0PWSZ"));
+    assert!(data.len() < long.len());
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_field(field_type, &data).unwrap();
+        // Written without marker, e.g. by Password Safe
+        db.write_field(0x05, long.as_bytes()).unwrap();
+        db.write_record_field(&PwsafeRecordField::Notes("short".to_string())).unwrap();
+        db.finish().unwrap();
+    }
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    db.read_header().unwrap();
+    let fields: Vec<_> = db.typed_fields().map(Result::unwrap).collect();
+    assert_eq!(
+        fields,
+        vec![
+            PwsafeRecordField::Notes(long.clone()),
+            PwsafeRecordField::Notes(long),
+            PwsafeRecordField::Notes("short".to_string()),
+            PwsafeRecordField::EndOfRecord,
+        ]
+    );
+    db.verify().unwrap();
+
+    assert_eq!(PwsafeRecordField::Notes("short".to_string()).to_field().1, b"short".to_vec());
+    assert!(PwsafeRecordField::new(0x05, b"\0PWSZgarbage".to_vec()).is_err());
+}
@@ -0,0 +1,50 @@
+extern crate pwsafer;
+
+use pwsafer::{read_csv, write_csv, PwsafeDatabase, PwsafeWriter};
+
+const CSV: &str = "title,Username,Password,url,Notes,Extra\r
+Mail,alice,secret,https://mail.example,,ignored\r
+\"Bank, online\",bob,\"pa\"\"ss\",,\"two\nlines\",\r
+";
+
+#[test]
+fn read_csv_records() {
+    let records = read_csv(CSV.as_bytes()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].title(), Some("Mail"));
+    assert_eq!(records[0].url(), Some("https://mail.example"));
+    assert_eq!(records[1].title(), Some("Bank, online"));
+    assert_eq!(records[1].username(), Some("bob"));
+    assert!(records[1].fields().contains(&pwsafer::PwsafeRecordField::Password("pa\"ss".to_string())));
+    assert!(records[1].fields().contains(&pwsafer::PwsafeRecordField::Notes("two\nlines".to_string())));
+    assert_eq!(records[1].url(), None);
+
+    assert!(read_csv("Title,Username\nx,y\n".as_bytes()).is_err());
+    assert!(read_csv("Title,Password\n\"x,y\n".as_bytes()).is_err());
+}
+
+#[test]
+fn csv_round_trip() {
+    let records = read_csv(CSV.as_bytes()).unwrap();
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        for record in &records {
+            db.write_record(record).unwrap();
+        }
+        db.finish().unwrap();
+    }
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(db.records(), &records[..]);
+
+    let mut csv = Vec::new();
+    write_csv(db.records(), &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with("Group,Title,Username,Password,URL,Notes\n"));
+    assert!(csv.contains("\"Bank, online\",bob,\"pa\"\"ss\",,\"two\nlines\"\n"));
+
+    let again = read_csv(csv.as_bytes()).unwrap();
+    assert_eq!(again.len(), 2);
+    assert_eq!(again[1].title(), Some("Bank, online"));
+}
@@ -0,0 +1,310 @@
+extern crate pwsafer;
+
+use pwsafer::{PwsafeDatabase, PwsafeHeaderField, PwsafeRecordField, PwsafeWriter, SearchOptions};
+use std::fs::File;
+use std::io::BufReader;
+
+fn entry(group: &str, title: &str, username: &str, url: &str) -> Vec<PwsafeRecordField> {
+    vec![
+        PwsafeRecordField::Group(group.to_string()),
+        PwsafeRecordField::Title(title.to_string()),
+        PwsafeRecordField::Username(username.to_string()),
+        PwsafeRecordField::Url(url.to_string()),
+        PwsafeRecordField::EndOfRecord,
+    ]
+}
+
+fn database() -> PwsafeDatabase {
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        let entries = [
+            entry("Mail", "Gmail", "alice", "https://mail.google.com"),
+            entry("Banking.Online", "My Bank", "alice.smith", "https://bank.example"),
+            entry("Social", "Forum", "bob", "https://forum.example/mail"),
+        ];
+        for field in entries.iter().flatten() {
+            let (field_type, data) = field.to_field();
+            db.write_field(field_type, &data).unwrap();
+        }
+        db.finish().unwrap();
+    }
+    PwsafeDatabase::read(&buf[..], b"password").unwrap()
+}
+
+#[test]
+fn read_fixture() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let db = PwsafeDatabase::read(file, b"password").unwrap();
+    assert_eq!(db.header().version(), Some(0x030d));
+    assert_eq!(db.records().len(), 1);
+    assert_eq!(db.records()[0].title(), Some("test"));
+}
+
+#[test]
+fn find() {
+    let db = database();
+    let titles = |records: Vec<&pwsafer::PwsafeRecord>| -> Vec<String> {
+        records.iter().map(|r| r.title().unwrap().to_string()).collect()
+    };
+    assert_eq!(titles(db.find("MAIL")), vec!["Gmail", "Forum"]);
+    assert_eq!(titles(db.find("alice")), vec!["Gmail", "My Bank"]);
+    assert!(db.find("banking").is_empty());
+
+    let options = SearchOptions {
+        title: false,
+        username: false,
+        url: false,
+        group: true,
+    };
+    assert_eq!(titles(db.find_with("banking", &options)), vec!["My Bank"]);
+    assert!(db.find_with("gmail", &options).is_empty());
+}
+
+#[test]
+fn save() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeDatabase::read(file, b"password").unwrap();
+    db.header_mut().fields_mut().push(PwsafeHeaderField::Blob {
+        field_type: 0x7f,
+        data: b"unknown".to_vec(),
+    });
+    db.records_mut()[0].fields_mut().push(PwsafeRecordField::Notes("changed".to_string()));
+
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"secret", 2048).unwrap();
+    let saved = PwsafeDatabase::read(&buf[..], b"secret").unwrap();
+    assert_eq!(saved, db);
+    assert_eq!(pwsafer::estimate_size(db.header(), db.records()), buf.len());
+}
+
+#[test]
+fn empty() {
+    let mut buf = Vec::new();
+    let uuid = {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        let uuid = db.write_empty_header().unwrap();
+        db.finish().unwrap();
+        uuid
+    };
+
+    let mut reader = pwsafer::PwsafeReader::new(&buf[..], b"password").unwrap();
+    reader.read_header().unwrap();
+    assert!(reader.read_record().unwrap().is_none());
+    reader.verify().unwrap();
+
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(db.header().version(), Some(0x030e));
+    assert_eq!(db.header().database_uuid(), Some(uuid));
+    assert!(db.records().is_empty());
+    assert!(db.find("").is_empty());
+
+    let mut saved = Vec::new();
+    db.save(&mut saved, b"password", 2048).unwrap();
+    assert_eq!(PwsafeDatabase::read(&saved[..], b"password").unwrap(), db);
+}
+
+#[test]
+fn unknown_fields_round_trip() {
+    // The supported format version defines no attachment fields, vendor attachment data ends up
+    // in `Blob`s and must survive a rewrite unchanged
+    let attachment: Vec<u8> = (0..100_000).map(|i| (i % 256) as u8).collect();
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_field(0x03, b"with attachment").unwrap();
+        db.write_field(0x1a, &[0xab; 16]).unwrap();
+        db.write_field(0xdf, &attachment).unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+    }
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    let blobs: Vec<&PwsafeRecordField> = db.records()[0]
+        .fields()
+        .iter()
+        .filter(|f| matches!(f, PwsafeRecordField::Blob { .. }))
+        .collect();
+    assert_eq!(blobs.len(), 2);
+    assert_eq!(blobs[1].to_field(), (0xdf, attachment));
+
+    let mut saved = Vec::new();
+    db.save(&mut saved, b"password", 2048).unwrap();
+    assert_eq!(PwsafeDatabase::read(&saved[..], b"password").unwrap(), db);
+}
+
+#[test]
+fn estimate_size() {
+    let db = database();
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"password", 2048).unwrap();
+    assert_eq!(pwsafer::estimate_size(db.header(), db.records()), buf.len());
+    assert_eq!(pwsafer::estimate_size(&Default::default(), &[]), 152 + 16 + 48);
+}
+
+#[test]
+fn anonymize() {
+    let mut db = PwsafeDatabase::new(
+        Default::default(),
+        vec![pwsafer::PwsafeRecord::new(vec![
+            PwsafeRecordField::Uuid([1; 16]),
+            PwsafeRecordField::Group("Banking".to_string()),
+            PwsafeRecordField::Title("My Bank".to_string()),
+            PwsafeRecordField::Password("secret".to_string()),
+            PwsafeRecordField::Notes("PIN 1234".to_string()),
+            PwsafeRecordField::PasswordHistory("1ff0001".to_string()),
+            PwsafeRecordField::CreditCardNumber("4111111111111111".to_string()),
+            PwsafeRecordField::TwoFactorKey(vec![1, 2, 3]),
+        ])],
+    );
+    db.anonymize();
+    let record = &db.records()[0];
+    assert_eq!(record.title(), Some("My Bank"));
+    assert_eq!(record.group(), Some("Banking"));
+    assert_eq!(record.two_factor_key(), None);
+    assert_eq!(record.fields().len(), 6);
+    for field in record.fields() {
+        match field {
+            PwsafeRecordField::Uuid(uuid) => assert_ne!(uuid, &[1; 16]),
+            PwsafeRecordField::Password(s)
+            | PwsafeRecordField::Notes(s)
+            | PwsafeRecordField::CreditCardNumber(s) => assert!(s.is_empty()),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn create() {
+    let (db, uuid) = PwsafeDatabase::create();
+    assert_eq!(db.header().database_uuid(), Some(uuid));
+    assert_eq!(db.header().version(), Some(0x030e));
+    assert!(db.records().is_empty());
+    assert_ne!(PwsafeDatabase::create().1, uuid);
+
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"password", 2048).unwrap();
+    assert_eq!(PwsafeDatabase::read(&buf[..], b"password").unwrap(), db);
+}
+
+#[test]
+fn filters_xml() {
+    let xml = "<?xml version=\"1.0\"?><filters><filter filtername=\"Mail\"/></filters>";
+    let (mut db, _) = PwsafeDatabase::create();
+    assert_eq!(db.header().filters_xml(), None);
+    db.header_mut().set_filters_xml(Some("<filters/>".to_string()));
+    db.header_mut().set_filters_xml(Some(xml.to_string()));
+    assert_eq!(db.header().fields().len(), 3);
+
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"password", 2048).unwrap();
+    let mut saved = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(saved.header().filters_xml(), Some(xml));
+    saved.header_mut().set_filters_xml(None);
+    assert_eq!(saved.header().filters_xml(), None);
+    assert_eq!(saved.header().fields().len(), 2);
+}
+
+#[test]
+fn groups() {
+    let mut db = database();
+    db.header_mut().fields_mut().push(PwsafeHeaderField::EmptyGroups("Banking.Old".to_string()));
+    db.header_mut().fields_mut().push(PwsafeHeaderField::EmptyGroups("v1\\.2".to_string()));
+    db.records_mut().push(pwsafer::PwsafeRecord::new(vec![PwsafeRecordField::Title(
+        "Top".to_string(),
+    )]));
+
+    let root = db.groups();
+    let names: Vec<&str> = root.children.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(names, vec!["Banking", "Mail", "Social", "v1.2"]);
+    assert_eq!(root.records, vec![3]);
+    let banking = root.find("Banking").unwrap();
+    assert!(banking.records.is_empty());
+    assert_eq!(banking.children.len(), 2);
+    assert_eq!(root.find("Banking.Online").unwrap().records, vec![1]);
+    assert!(root.find("Banking.Old").unwrap().records.is_empty());
+    assert_eq!(root.find("Mail").unwrap().records, vec![0]);
+    assert!(root.find("Banking.New").is_none());
+    assert!(root.find("v1\\.2").unwrap().children.is_empty());
+
+    assert_eq!(pwsafer::GroupTree::split_path("a\\.b.c"), vec!["a.b", "c"]);
+    assert!(pwsafer::GroupTree::split_path("").is_empty());
+}
+
+#[test]
+fn last_save_app() {
+    let (mut db, _) = PwsafeDatabase::create();
+    assert_eq!(db.header().last_save_app(), None);
+    db.header_mut().set_last_save_app(Some("Password Safe V3.65".to_string()));
+    db.header_mut().set_last_save_app(Some(pwsafer::APP_NAME.to_string()));
+    assert_eq!(db.header().fields().len(), 3);
+    assert!(pwsafer::APP_NAME.starts_with("pwsafer "));
+
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"password", 2048).unwrap();
+    let saved = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(saved.header().last_save_app(), Some(pwsafer::APP_NAME));
+
+    let mut buf = Vec::new();
+    {
+        let mut writer = pwsafer::PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        writer.set_last_save_app(Some("converter 1.0".to_string()));
+        writer.write_empty_header().unwrap();
+        writer.finish().unwrap();
+    }
+    let saved = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(saved.header().last_save_app(), Some("converter 1.0"));
+    assert!(saved.header().version().is_some());
+}
+
+#[test]
+fn revision() {
+    let (mut db, _) = PwsafeDatabase::create();
+    assert_eq!(db.header().revision(), None);
+    assert_eq!(db.header_mut().bump_revision(), 1);
+    assert_eq!(db.header_mut().bump_revision(), 2);
+    assert_eq!(db.header().fields().len(), 3);
+
+    let mut buf = Vec::new();
+    db.save(&mut buf, b"password", 2048).unwrap();
+    let mut saved = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(saved.header().revision(), Some(2));
+    assert!(saved.header().fields().contains(&PwsafeHeaderField::Blob {
+        field_type: 0xde,
+        data: vec![2, 0, 0, 0, 0, 0, 0, 0],
+    }));
+    saved.header_mut().set_revision(None);
+    assert_eq!(saved.header().revision(), None);
+    assert_eq!(saved.header().fields().len(), 2);
+
+    let mut buf = Vec::new();
+    {
+        let mut writer = pwsafer::PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        writer.set_revision(Some(41));
+        writer.write_empty_header().unwrap();
+        writer.finish().unwrap();
+    }
+    let saved = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(saved.header().revision(), Some(41));
+}
+
+#[test]
+fn borrowed_accessors() {
+    let db = database();
+    // The strings borrow from the records, nothing is cloned
+    let titles: Vec<&str> = db.records().iter().filter_map(|r| r.title()).collect();
+    assert_eq!(titles.len(), db.records().len());
+
+    let record = pwsafer::PwsafeRecordBuilder::new()
+        .title("Mail")
+        .password("secret")
+        .notes("some notes")
+        .field(PwsafeRecordField::EmailAddress("alice@example.com".to_string()))
+        .build()
+        .unwrap();
+    assert_eq!(record.password(), Some("secret"));
+    assert_eq!(record.notes(), Some("some notes"));
+    assert_eq!(record.email(), Some("alice@example.com"));
+    assert_eq!(record.url(), None);
+}
@@ -0,0 +1,291 @@
+extern crate pwsafer;
+
+use pwsafer::{
+    FieldError, HeaderFieldType, PwsafeHeaderField, PwsafeReader, PwsafeRecord, PwsafeRecordField,
+    RecordFieldType,
+};
+use std::fs::File;
+use std::io::BufReader;
+
+#[test]
+fn field_types() {
+    assert_eq!(HeaderFieldType::from_u8(0x09), Some(HeaderFieldType::DatabaseName));
+    assert_eq!(HeaderFieldType::from_u8(0x0c), None);
+    assert_eq!(HeaderFieldType::EndOfHeader.as_u8(), 0xff);
+    assert_eq!(RecordFieldType::from_u8(0x20), Some(RecordFieldType::QrCode));
+    assert_eq!(RecordFieldType::from_u8(0x24), Some(RecordFieldType::TotpStartTime));
+    assert_eq!(RecordFieldType::from_u8(0x25), None);
+    assert_eq!(RecordFieldType::from_u8(0x0b), None);
+    assert_eq!(RecordFieldType::Password.as_u8(), 0x06);
+}
+
+#[test]
+fn to_field_round_trip() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReader::new(file, b"password").unwrap();
+    let mut in_header = true;
+    while let Some((field_type, data)) = db.read_field().unwrap() {
+        let field = (field_type, data.clone());
+        if in_header {
+            let parsed = PwsafeHeaderField::new(field_type, data).unwrap();
+            assert_eq!(parsed.to_field(), field);
+            in_header = field_type != 0xff;
+        } else {
+            let parsed = PwsafeRecordField::new(field_type, data).unwrap();
+            assert_eq!(parsed.to_field(), field);
+            assert_eq!(parsed.field_type(), field_type);
+        }
+    }
+}
+
+#[test]
+fn field_type() {
+    for field_type in 0..=0xff {
+        // Some length fits every type, the contents are valid UTF-8
+        for len in &[0, 1, 2, 4, 16] {
+            if let Ok(field) = PwsafeRecordField::new(field_type, vec![b'a'; *len]) {
+                assert_eq!(field.field_type(), field_type);
+                assert_eq!(field.to_field().0, field_type);
+            }
+        }
+    }
+}
+
+#[test]
+fn blob_keeps_type() {
+    let field = PwsafeRecordField::new(0xdf, vec![1, 2, 3]).unwrap();
+    assert_eq!(field.to_field(), (0xdf, vec![1, 2, 3]));
+    assert_eq!(field.field_type(), 0xdf);
+    let field = PwsafeHeaderField::new(0x0c, vec![4]).unwrap();
+    assert_eq!(field.to_field(), (0x0c, vec![4]));
+}
+
+#[test]
+fn totp_fields() {
+    let field = PwsafeRecordField::new(0x22, vec![8]).unwrap();
+    assert_eq!(field, PwsafeRecordField::TotpLength(8));
+    assert_eq!(field.to_field(), (0x22, vec![8]));
+    let field = PwsafeRecordField::new(0x24, vec![0x10, 0, 0, 0]).unwrap();
+    assert_eq!(field, PwsafeRecordField::TotpStartTime(16));
+    assert_eq!(field.to_field(), (0x24, vec![0x10, 0, 0, 0]));
+    assert!(PwsafeRecordField::new(0x23, vec![30, 0]).is_err());
+    let field = PwsafeRecordField::new(0x25, vec![1]).unwrap();
+    assert_eq!(field.to_field(), (0x25, vec![1]));
+}
+
+#[test]
+fn two_factor_key_base32() {
+    let field = PwsafeRecordField::two_factor_key_from_base32("jbsw y3dp ehpk 3pxp").unwrap();
+    assert_eq!(field, PwsafeRecordField::TwoFactorKey(b"Hello!\xde\xad\xbe\xef".to_vec()));
+    let field = PwsafeRecordField::two_factor_key_from_base32("MZXW6===").unwrap();
+    assert_eq!(field, PwsafeRecordField::TwoFactorKey(b"foo".to_vec()));
+    assert!(PwsafeRecordField::two_factor_key_from_base32("MZXW1").is_err());
+
+    let record = PwsafeRecord::new(vec![PwsafeRecordField::TwoFactorKey(b"foob".to_vec())]);
+    assert_eq!(record.two_factor_key(), Some(&b"foob"[..]));
+    assert_eq!(record.two_factor_key_base32().as_deref(), Some("MZXW6YQ"));
+    assert_eq!(PwsafeRecord::default().two_factor_key_base32(), None);
+}
+
+#[test]
+fn recently_used_entries() {
+    let uuids = [[0x01; 16], [0xab; 16]];
+    let field = PwsafeHeaderField::recently_used_entries(&uuids).unwrap();
+    let s = match field {
+        PwsafeHeaderField::RecentlyUsedEntries(ref s) => s.clone(),
+        _ => panic!("expected RecentlyUsedEntries"),
+    };
+    assert_eq!(&s[..4], "0201");
+    assert_eq!(s.len(), 2 + 2 * 32);
+    assert_eq!(PwsafeHeaderField::parse_recently_used_entries(&s).unwrap(), uuids);
+    assert_eq!(PwsafeHeaderField::parse_recently_used_entries("00").unwrap(), Vec::<[u8; 16]>::new());
+    assert!(PwsafeHeaderField::parse_recently_used_entries("01abcd").is_err());
+    assert!(PwsafeHeaderField::parse_recently_used_entries(&s.replace('a', "g")).is_err());
+}
+
+#[test]
+fn uuid_constructors() {
+    assert_eq!(PwsafeHeaderField::uuid(&[1; 16]).unwrap(), PwsafeHeaderField::Uuid([1; 16]));
+    assert_eq!(PwsafeRecordField::uuid(&[2; 16]).unwrap(), PwsafeRecordField::Uuid([2; 16]));
+    for len in &[0, 15, 17] {
+        match PwsafeRecordField::uuid(&vec![0; *len]) {
+            Err(FieldError::InvalidLength) => (),
+            _ => panic!("expected InvalidLength"),
+        }
+        assert!(PwsafeHeaderField::uuid(&vec![0; *len]).is_err());
+    }
+}
+
+#[test]
+fn validate_timestamps() {
+    let record = |fields: Vec<PwsafeRecordField>| PwsafeRecord::new(fields);
+    let invalid = |r: PwsafeRecord| match r.validate_timestamps() {
+        Err(FieldError::InvalidTimestamp(field_type)) => field_type,
+        _ => panic!("expected InvalidTimestamp"),
+    };
+
+    record(vec![
+        PwsafeRecordField::CreationTime(1_500_000_000),
+        PwsafeRecordField::LastModificationTime(1_600_000_000),
+        PwsafeRecordField::PasswordExpiryTime(2_000_000_000),
+    ])
+    .validate_timestamps()
+    .unwrap();
+    PwsafeRecord::default().validate_timestamps().unwrap();
+
+    assert_eq!(
+        invalid(record(vec![PwsafeRecordField::LastAccessTime(0)])),
+        RecordFieldType::LastAccessTime
+    );
+    assert_eq!(
+        invalid(record(vec![PwsafeRecordField::PasswordModificationTime(u32::MAX)])),
+        RecordFieldType::PasswordModificationTime
+    );
+    assert_eq!(
+        invalid(record(vec![
+            PwsafeRecordField::LastModificationTime(1_500_000_000),
+            PwsafeRecordField::CreationTime(1_600_000_000),
+        ])),
+        RecordFieldType::CreationTime
+    );
+}
+
+#[test]
+fn content_hash() {
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Title("Mail".to_string()),
+        PwsafeRecordField::Username("alice".to_string()),
+        PwsafeRecordField::Password("secret".to_string()),
+        PwsafeRecordField::CreationTime(1_600_000_000),
+    ]);
+    let copy = PwsafeRecord::new(vec![
+        PwsafeRecordField::Password("secret".to_string()),
+        PwsafeRecordField::Username("alice".to_string()),
+        PwsafeRecordField::Title("Mail".to_string()),
+        PwsafeRecordField::Uuid([2; 16]),
+        PwsafeRecordField::Notes(String::new()),
+    ]);
+    assert_eq!(record.content_hash(), copy.content_hash());
+
+    // Content moved between fields is different content
+    let moved = PwsafeRecord::new(vec![
+        PwsafeRecordField::Title("Mail".to_string()),
+        PwsafeRecordField::Username("alicesecret".to_string()),
+    ]);
+    assert_ne!(record.content_hash(), moved.content_hash());
+    assert_ne!(record.content_hash(), PwsafeRecord::default().content_hash());
+}
+
+#[test]
+fn display() {
+    let field = PwsafeRecordField::Title("Gmail".to_string());
+    assert_eq!(field.to_string(), "Title: Gmail");
+    let field = PwsafeRecordField::CreationTime(1_615_713_993);
+    assert_eq!(field.to_string(), "Created: 2021-03-14 09:26:33");
+    let field = PwsafeRecordField::LastAccessTime(951_782_400);
+    assert_eq!(field.to_string(), "Last accessed: 2000-02-29 00:00:00");
+    let field = PwsafeRecordField::Password("secret".to_string());
+    assert_eq!(field.to_string(), "Password: ********");
+    assert_eq!(format!("{:#}", field), "Password: secret");
+    let field = PwsafeRecordField::TwoFactorKey(b"foo".to_vec());
+    assert_eq!(format!("{:#}", field), "Two-factor key: MZXW6");
+    let field = PwsafeRecordField::Uuid([
+        0x12, 0x09, 0xa0, 0xac, 0x5c, 0xd0, 0x4a, 0xfc, 0x98, 0xf7, 0xdf, 0xec, 0x6e, 0x16, 0x50, 0x42,
+    ]);
+    assert_eq!(field.to_string(), "UUID: 1209a0ac-5cd0-4afc-98f7-dfec6e165042");
+
+    assert_eq!(PwsafeHeaderField::Version(0x030d).to_string(), "Version: 0x030d");
+    let field = PwsafeHeaderField::Blob {
+        field_type: 0x7f,
+        data: vec![1, 2, 3],
+    };
+    assert_eq!(field.to_string(), "Unknown field 0x7f: 3 bytes");
+}
+
+#[test]
+fn touch() {
+    use pwsafer::{PwsafeDatabase, PwsafeWriter};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Title("title".to_string()),
+    ]);
+    record.touch(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    assert_eq!(record.fields()[2], PwsafeRecordField::LastAccessTime(1_000_000));
+    record.touch(UNIX_EPOCH + Duration::from_secs(2_000_000));
+    assert_eq!(record.fields().len(), 3);
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_record(&record).unwrap();
+        db.finish().unwrap();
+    }
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert!(db.records()[0]
+        .fields()
+        .contains(&PwsafeRecordField::LastAccessTime(2_000_000)));
+}
+
+#[test]
+fn partition_fields() {
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Blob {
+            field_type: 0x7f,
+            data: b"unknown".to_vec(),
+        },
+        PwsafeRecordField::Title("title".to_string()),
+    ]);
+    let (known, unknown) = record.partition_fields();
+    assert_eq!(
+        known,
+        vec![PwsafeRecordField::Uuid([1; 16]), PwsafeRecordField::Title("title".to_string())]
+    );
+    assert_eq!(unknown, vec![(0x7f, b"unknown".to_vec())]);
+}
+
+#[test]
+fn merge_fields() {
+    let mut record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Title("title".to_string()),
+        PwsafeRecordField::Url("http://old.example".to_string()),
+        PwsafeRecordField::Blob {
+            field_type: 0x7f,
+            data: b"old".to_vec(),
+        },
+    ]);
+    record.merge_fields(vec![
+        PwsafeRecordField::Url("https://new.example".to_string()),
+        PwsafeRecordField::Blob {
+            field_type: 0x7f,
+            data: b"new".to_vec(),
+        },
+        PwsafeRecordField::Blob {
+            field_type: 0x7e,
+            data: b"other".to_vec(),
+        },
+        PwsafeRecordField::Username("alice".to_string()),
+    ]);
+    assert_eq!(
+        record.fields(),
+        &[
+            PwsafeRecordField::Uuid([1; 16]),
+            PwsafeRecordField::Title("title".to_string()),
+            PwsafeRecordField::Url("https://new.example".to_string()),
+            PwsafeRecordField::Blob {
+                field_type: 0x7f,
+                data: b"new".to_vec(),
+            },
+            PwsafeRecordField::Blob {
+                field_type: 0x7e,
+                data: b"other".to_vec(),
+            },
+            PwsafeRecordField::Username("alice".to_string()),
+        ][..]
+    );
+}
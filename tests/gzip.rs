@@ -0,0 +1,54 @@
+#![cfg(feature = "flate2")]
+
+extern crate flate2;
+extern crate pwsafer;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use pwsafer::{PwsafeReader, PwsafeWriter, ReaderError};
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn read_gz() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut db = PwsafeReader::new_gz(&compressed[..], b"password").unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030d);
+    db.verify_all().unwrap();
+
+    match PwsafeReader::new(&compressed[..], b"password") {
+        Err(ReaderError::GzipCompressed) => (),
+        _ => panic!("expected GzipCompressed"),
+    }
+    match PwsafeReader::new_gz(&data[..], b"password") {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+}
+
+#[test]
+fn write_gz() {
+    let mut buf = Vec::new();
+    let uuid = {
+        let mut db = PwsafeWriter::new_gz(&mut buf, 2048, b"password").unwrap();
+        let uuid = db.write_empty_header().unwrap();
+        db.write_field(0x03, b"title").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+        uuid
+    };
+    assert_eq!(&buf[..2], &[0x1f, 0x8b]);
+
+    let mut db = PwsafeReader::new_gz(&buf[..], b"password").unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030e);
+    assert_eq!(db.read_field().unwrap().unwrap(), (0x01, uuid.to_vec()));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0xff, vec![]));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0x03, b"title".to_vec()));
+    assert_eq!(db.read_field().unwrap().unwrap(), (0xff, vec![]));
+    assert!(db.read_field().unwrap().is_none());
+    db.verify().unwrap();
+}
@@ -0,0 +1,90 @@
+extern crate pwsafer;
+
+use pwsafer::{PasswordHistory, PasswordHistoryEntry, PwsafeRecord, PwsafeRecordField};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn parse_and_encode() {
+    let s = "10302\
+             5f5e10000006hunter\
+             5f5e20000002\u{e4}x";
+    let history = PasswordHistory::parse(s).unwrap();
+    assert!(history.enabled);
+    assert_eq!(history.max_entries, 3);
+    assert_eq!(
+        history.entries,
+        vec![
+            PasswordHistoryEntry {
+                changed: 0x5f5e1000,
+                password: "hunter".to_string(),
+            },
+            PasswordHistoryEntry {
+                changed: 0x5f5e2000,
+                password: "\u{e4}x".to_string(),
+            },
+        ]
+    );
+    assert_eq!(history.encode().unwrap(), s);
+
+    assert_eq!(PasswordHistory::parse("00a00").unwrap().max_entries, 10);
+    for bad in &["", "2ff00", "1ff01", "10301000000000005abc", "1ff00x"] {
+        assert!(PasswordHistory::parse(bad).is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn push_trims_to_max() {
+    let mut history = PasswordHistory::new(2);
+    for (i, password) in ["one", "two", "three"].iter().enumerate() {
+        history.push(password, i as u32);
+    }
+    let passwords: Vec<&str> = history.entries.iter().map(|e| e.password.as_str()).collect();
+    assert_eq!(passwords, vec!["two", "three"]);
+
+    let mut history = PasswordHistory::new(0);
+    history.push("one", 1);
+    assert!(history.entries.is_empty());
+
+    let mut history = PasswordHistory {
+        enabled: false,
+        ..PasswordHistory::new(5)
+    };
+    history.push("one", 1);
+    assert!(history.entries.is_empty());
+}
+
+#[test]
+fn set_password() {
+    let mut record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Password("first".to_string()),
+        PwsafeRecordField::PasswordHistory(PasswordHistory::new(2).encode().unwrap()),
+    ]);
+    record.set_password("second").unwrap();
+    record.set_password("second").unwrap();
+    record.set_password("third").unwrap();
+    record.set_password("fourth").unwrap();
+    let history = record.password_history().unwrap().unwrap();
+    let passwords: Vec<&str> = history.entries.iter().map(|e| e.password.as_str()).collect();
+    assert_eq!(passwords, vec!["second", "third"]);
+    assert!(record.fields().contains(&PwsafeRecordField::Password("fourth".to_string())));
+    assert!(record
+        .fields()
+        .iter()
+        .any(|f| matches!(f, PwsafeRecordField::PasswordModificationTime(_))));
+
+    let when = UNIX_EPOCH + Duration::from_secs(0x1234);
+    record.push_password_history("manual", when).unwrap();
+    let history = record.password_history().unwrap().unwrap();
+    assert_eq!(history.entries[1].changed, 0x1234);
+
+    // Without a history field nothing is recorded
+    let mut record = PwsafeRecord::new(vec![PwsafeRecordField::Password("first".to_string())]);
+    record.set_password("second").unwrap();
+    assert_eq!(record.password_history().unwrap(), None);
+
+    let mut record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Password("first".to_string()),
+        PwsafeRecordField::PasswordHistory("bogus".to_string()),
+    ]);
+    assert!(record.set_password("second").is_err());
+}
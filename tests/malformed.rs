@@ -0,0 +1,355 @@
+extern crate pwsafer;
+
+use pwsafer::{
+    parse_database, FieldError, FormatVersion, PreamblePart, PwsafeHeaderField, PwsafeReader, PwsafeRecordField, ReaderError,
+};
+use std::fs;
+
+fn corpus(name: &str) -> Vec<u8> {
+    fs::read(format!("tests/corpus/{}.psafe3", name)).unwrap()
+}
+
+/// Reads the whole database the way a typical consumer does, parsing every field.
+fn read_all(data: &[u8]) -> Result<(), String> {
+    let mut db = PwsafeReader::new(data, b"password").map_err(|e| e.to_string())?;
+    db.read_version().map_err(|e| e.to_string())?;
+    let mut in_header = true;
+    while let Some((field_type, field_data)) = db.read_field().map_err(|e| e.to_string())? {
+        if in_header {
+            PwsafeHeaderField::new(field_type, field_data).map_err(|e| e.to_string())?;
+            in_header = field_type != 0xff;
+        } else {
+            PwsafeRecordField::new(field_type, field_data).map_err(|e| e.to_string())?;
+        }
+    }
+    db.verify().map_err(|e| e.to_string())
+}
+
+#[test]
+fn bad_tag() {
+    match PwsafeReader::new(&corpus("bad_tag")[..], b"password") {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+    match PwsafeReader::new(&b""[..], b"password") {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+}
+
+#[test]
+fn truncated_preamble() {
+    match PwsafeReader::new(&corpus("truncated_preamble")[..], b"password") {
+        Err(ReaderError::HeaderTooShort(_)) => (),
+        _ => panic!("expected HeaderTooShort"),
+    }
+    match PwsafeReader::new(&b"PWS3\x01\x02\x03\x04\x05\x06"[..], b"password") {
+        Err(ReaderError::HeaderTooShort(PreamblePart::Salt)) => (),
+        _ => panic!("expected HeaderTooShort"),
+    }
+}
+
+#[test]
+fn missing_eof() {
+    match PwsafeReader::new(&corpus("missing_eof")[..], b"password") {
+        Err(ReaderError::MissingEof) => (),
+        _ => panic!("expected MissingEof"),
+    }
+}
+
+#[test]
+fn missing_version() {
+    let data = corpus("missing_version");
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    match db.read_version() {
+        Err(ReaderError::InvalidHeader) => (),
+        _ => panic!("expected InvalidHeader"),
+    }
+}
+
+#[test]
+fn invalid_field_length() {
+    for name in &["truncated_field", "giant_length"] {
+        let data = corpus(name);
+        let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+        db.read_version().unwrap();
+        assert_eq!(db.read_field().unwrap().unwrap().0, 0xff);
+        match db.read_field() {
+            Err(ReaderError::InvalidFieldLength) => (),
+            _ => panic!("expected InvalidFieldLength in {}", name),
+        }
+    }
+}
+
+#[test]
+fn non_utf8() {
+    let data = corpus("non_utf8");
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    db.read_version().unwrap();
+    db.read_field().unwrap().unwrap();
+    let (field_type, field_data) = db.read_field().unwrap().unwrap();
+    match PwsafeRecordField::new(field_type, field_data) {
+        Err(FieldError::FromUtf8Error(_)) => (),
+        _ => panic!("expected FromUtf8Error"),
+    }
+}
+
+#[test]
+fn bad_mac() {
+    let data = corpus("bad_mac");
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    while db.read_field().unwrap().is_some() {}
+    match db.verify() {
+        Err(ReaderError::MacError(_)) => (),
+        _ => panic!("expected MacError"),
+    }
+}
+
+#[test]
+fn corpus_never_panics() {
+    for entry in fs::read_dir("tests/corpus").unwrap() {
+        let data = fs::read(entry.unwrap().path()).unwrap();
+        assert!(read_all(&data).is_err());
+        assert!(parse_database(&data, b"password").is_err());
+    }
+}
+
+#[test]
+fn truncations_never_panic() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    assert!(read_all(&data).is_ok());
+    for len in 0..data.len() {
+        assert!(read_all(&data[..len]).is_err(), "truncated to {} bytes", len);
+        assert!(parse_database(&data[..len], b"password").is_err());
+    }
+}
+
+#[test]
+fn bit_flips_never_panic() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    // The preamble is covered by the password check, flip bits in the encrypted part
+    for pos in 152..data.len() {
+        let mut corrupt = data.clone();
+        corrupt[pos] ^= 0x01;
+        let _ = read_all(&corrupt);
+        let _ = parse_database(&corrupt, b"password");
+    }
+}
+
+#[test]
+fn parse_database_limits_iterations() {
+    let mut data = fs::read("tests/pwsafe.psafe3").unwrap();
+    data[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+    match parse_database(&data, b"password") {
+        Err(ReaderError::ExcessiveIterations(u32::MAX)) => (),
+        res => panic!("expected ExcessiveIterations, got {:?}", res.map(|_| ())),
+    }
+}
+
+#[test]
+fn recovery() {
+    use pwsafer::PwsafeReaderBuilder;
+
+    let builder = PwsafeReaderBuilder::new().recovery(true);
+
+    // The EOF block and HMAC are gone, the fields are still there
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut db = builder.open(&data[..data.len() - 48], b"password").unwrap();
+    db.read_header().unwrap();
+    assert_eq!(db.read_record().unwrap().unwrap().title(), Some("test"));
+    assert!(db.verify().is_err());
+
+    for name in &["missing_eof", "giant_length", "truncated_field", "bad_mac"] {
+        let data = corpus(name);
+        let mut db = builder.open(&data[..], b"password").unwrap();
+        while db.read_field().unwrap().is_some() {}
+        assert!(db.verify().is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn truncated_mac() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    for cut in &[1, 5, 31, 32] {
+        let mut db = PwsafeReader::new(&data[..data.len() - cut], b"password").unwrap();
+        match db.verify_all() {
+            Err(ReaderError::InvalidMacLength) => (),
+            _ => panic!("expected InvalidMacLength with {} bytes cut", cut),
+        }
+    }
+}
+
+#[test]
+fn scan() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let report = pwsafer::scan(&data[..], b"password").unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.version, Some(0x030d));
+    assert_eq!(report.record_count, 1);
+    assert!(report.unknown_field_types.is_empty());
+
+    let report = pwsafer::scan(&corpus("bad_mac")[..], b"password").unwrap();
+    assert!(!report.hmac_valid);
+    assert!(report.parse_errors.is_empty());
+
+    let report = pwsafer::scan(&corpus("non_utf8")[..], b"password").unwrap();
+    match report.parse_errors[..] {
+        [ReaderError::FieldError(FieldError::FromUtf8Error(_))] => (),
+        _ => panic!("expected FromUtf8Error, got {:?}", report.parse_errors),
+    }
+
+    for name in &["missing_eof", "giant_length", "truncated_field", "missing_version"] {
+        let report = pwsafer::scan(&corpus(name)[..], b"password").unwrap();
+        assert!(!report.is_ok(), "{}", name);
+    }
+
+    match pwsafer::scan(&data[..], b"wrong") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[test]
+fn truncated_preamble_parts() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let parts = [
+        (20, PreamblePart::Salt, "salt"),
+        (38, PreamblePart::Iter, "iteration count"),
+        (50, PreamblePart::TrueHash, "password hash"),
+        (100, PreamblePart::K, "encryption key"),
+        (120, PreamblePart::L, "HMAC key"),
+        (140, PreamblePart::Iv, "IV"),
+    ];
+    for &(len, part, name) in &parts {
+        match PwsafeReader::new(&data[..len], b"password") {
+            Err(e @ ReaderError::HeaderTooShort(_)) => {
+                assert!(matches!(e, ReaderError::HeaderTooShort(p) if p == part));
+                assert_eq!(e.to_string(), format!("File too short, {} is missing", name))
+            }
+            _ => panic!("expected HeaderTooShort at {} bytes", len),
+        }
+    }
+}
+
+#[test]
+fn probe() {
+    let info = pwsafer::probe(&fs::read("tests/pwsafe.psafe3").unwrap()[..]).unwrap();
+    assert_eq!(info.version, FormatVersion::V3);
+    assert_eq!(info.iterations, Some(2048));
+
+    let info = pwsafer::probe(&b"PWS4"[..]).unwrap();
+    assert_eq!(info.version, FormatVersion::V4);
+    assert_eq!(info.iterations, None);
+
+    match pwsafer::probe(&corpus("bad_tag")[..]) {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+    match pwsafer::probe(&b"PWS3\x01\x02\x03\x04\x05\x06"[..]) {
+        Err(ReaderError::HeaderTooShort(PreamblePart::Salt)) => (),
+        _ => panic!("expected HeaderTooShort"),
+    }
+}
+
+#[test]
+fn header_field_context() {
+    use pwsafer::{HeaderFieldType, PwsafeWriter};
+
+    let mut data = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut data, 2048, b"password").unwrap();
+        db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+        db.write_field(0x09, b"Hello\xff").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+    }
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    match db.read_header() {
+        Err(e @ ReaderError::HeaderFieldError(HeaderFieldType::DatabaseName, FieldError::FromUtf8Error(_))) => {
+            assert_eq!(
+                e.to_string(),
+                "Failed to parse DatabaseName: invalid utf-8 sequence of 1 bytes from index 5"
+            );
+        }
+        res => panic!("expected HeaderFieldError, got {:?}", res.map(|_| ())),
+    }
+}
+
+#[test]
+fn key_unwrap_failed() {
+    let mut data = fs::read("tests/pwsafe.psafe3").unwrap();
+    // Encrypted K follows tag, salt, iteration count and password hash
+    data[72] ^= 1;
+    match PwsafeReader::new(&data[..], b"password") {
+        Err(ReaderError::KeyUnwrapFailed) => (),
+        _ => panic!("expected KeyUnwrapFailed"),
+    }
+    match PwsafeReader::new(&data[..], b"wrong") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[test]
+fn gzip_compressed() {
+    let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03";
+    match PwsafeReader::new(&data[..], b"password") {
+        Err(ReaderError::GzipCompressed) => (),
+        _ => panic!("expected GzipCompressed"),
+    }
+    match pwsafer::probe(&data[..]) {
+        Err(ReaderError::GzipCompressed) => (),
+        _ => panic!("expected GzipCompressed"),
+    }
+}
+
+#[test]
+fn check_password() {
+    use pwsafer::PwsafeReaderBuilder;
+
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    assert!(pwsafer::check_password(&data[..], b"password").unwrap());
+    assert!(!pwsafer::check_password(&data[..], b"wrong").unwrap());
+    // Only the preamble up to the password hash is needed
+    assert!(pwsafer::check_password(&data[..72], b"password").unwrap());
+
+    match pwsafer::check_password(&corpus("bad_tag")[..], b"password") {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+    match pwsafer::check_password(&data[..60], b"password") {
+        Err(ReaderError::HeaderTooShort(PreamblePart::TrueHash)) => (),
+        _ => panic!("expected HeaderTooShort"),
+    }
+
+    let builder = PwsafeReaderBuilder::new().max_iterations(4096);
+    assert!(builder.check_password(&data[..], b"password").unwrap());
+    let mut crafted = data.clone();
+    crafted[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+    match builder.check_password(&crafted[..], b"password") {
+        Err(ReaderError::ExcessiveIterations(u32::MAX)) => (),
+        _ => panic!("expected ExcessiveIterations"),
+    }
+}
+
+#[test]
+fn read_preamble() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let preamble = pwsafer::read_preamble(&data[..]).unwrap();
+    assert_eq!(&preamble.tag, b"PWS3");
+    assert_eq!(preamble.iter, 2048);
+    assert_eq!(&preamble.to_bytes()[..], &data[..152]);
+    assert_eq!(pwsafer::read_preamble(&data[..152]).unwrap(), preamble);
+
+    let db = PwsafeReader::new(&data[..], b"password").unwrap();
+    assert_eq!(&preamble.salt, db.get_salt());
+
+    match pwsafer::read_preamble(&data[..140]) {
+        Err(ReaderError::HeaderTooShort(PreamblePart::Iv)) => (),
+        _ => panic!("expected HeaderTooShort"),
+    }
+    match pwsafer::read_preamble(&corpus("bad_tag")[..]) {
+        Err(ReaderError::InvalidTag) => (),
+        _ => panic!("expected InvalidTag"),
+    }
+}
@@ -0,0 +1,142 @@
+extern crate pwsafer;
+extern crate rand;
+
+use pwsafer::{FieldError, NamedPasswordPolicy, PasswordPolicy, PwsafeRecord, PwsafeRecordField};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+fn policy() -> PasswordPolicy {
+    PasswordPolicy {
+        flags: PasswordPolicy::USE_LOWERCASE | PasswordPolicy::USE_DIGITS,
+        length: 20,
+        min_lowercase: 1,
+        min_uppercase: 0,
+        min_digits: 2,
+        min_symbols: 0,
+        symbols: String::new(),
+    }
+}
+
+#[test]
+fn parse_policy() {
+    let p = PasswordPolicy::parse("a000014001000002000").unwrap();
+    assert_eq!(p, policy());
+    assert!(p.has_flag(PasswordPolicy::USE_DIGITS));
+    assert!(!p.has_flag(PasswordPolicy::USE_SYMBOLS));
+    assert_eq!(p.encode(), "a000014001000002000");
+
+    for s in &["", "a00001400100000200", "a0000140010000020000", "+00001400100000200z"] {
+        match PasswordPolicy::parse(s) {
+            Err(FieldError::InvalidFormat) => (),
+            r => panic!("unexpected {:?} for {:?}", r, s),
+        }
+    }
+}
+
+#[test]
+fn named_policies() {
+    let mut strict = policy();
+    strict.symbols = "!#".to_string();
+    let policies = vec![
+        NamedPasswordPolicy { name: "Bank".to_string(), policy: policy() },
+        NamedPasswordPolicy { name: "Strict".to_string(), policy: strict },
+    ];
+    let s = NamedPasswordPolicy::encode_list(&policies).unwrap();
+    assert_eq!(s, "0204Banka0000140010000020000006Stricta00001400100000200002!#");
+    assert_eq!(NamedPasswordPolicy::parse_list(&s).unwrap(), policies);
+    assert!(NamedPasswordPolicy::parse_list("0104Bank").is_err());
+    assert!(NamedPasswordPolicy::parse_list(&(s + "x")).is_err());
+}
+
+#[test]
+fn effective_policy() {
+    let named = vec![NamedPasswordPolicy { name: "Bank".to_string(), policy: policy() }];
+
+    let record = PwsafeRecord::new(vec![PwsafeRecordField::Title("x".to_string())]);
+    assert_eq!(record.effective_policy(&named), None);
+
+    let record = PwsafeRecord::new(vec![PwsafeRecordField::PasswordPolicyName("Bank".to_string())]);
+    assert_eq!(record.effective_policy(&named), Some(policy()));
+
+    let record = PwsafeRecord::new(vec![PwsafeRecordField::PasswordPolicyName("Other".to_string())]);
+    assert_eq!(record.effective_policy(&named), None);
+
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::PasswordPolicyName("Bank".to_string()),
+        PwsafeRecordField::PasswordPolicy("f00000a001001001001".to_string()),
+        PwsafeRecordField::OwnSymbolsForPassword("$%".to_string()),
+    ]);
+    let p = record.effective_policy(&named).unwrap();
+    assert_eq!(p.length, 10);
+    assert_eq!(p.symbols, "$%");
+}
+
+fn count(password: &str, chars: &str) -> u16 {
+    password.chars().filter(|c| chars.contains(*c)).count() as u16
+}
+
+#[test]
+fn generate() {
+    let lower = "abcdefghijklmnopqrstuvwxyz";
+    let upper = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let digits = "0123456789";
+    let mut rng = StdRng::seed_from_u64(7);
+    let all = PasswordPolicy::USE_LOWERCASE
+        | PasswordPolicy::USE_UPPERCASE
+        | PasswordPolicy::USE_DIGITS
+        | PasswordPolicy::USE_SYMBOLS;
+    for &extra in &[0, PasswordPolicy::USE_EASY_VISION, PasswordPolicy::MAKE_PRONOUNCEABLE] {
+        let p = PasswordPolicy {
+            flags: all | extra,
+            length: 12,
+            min_lowercase: 2,
+            min_uppercase: 3,
+            min_digits: 2,
+            min_symbols: 1,
+            symbols: String::new(),
+        };
+        for _ in 0..100 {
+            let password = p.generate(&mut rng);
+            assert_eq!(password.chars().count(), 12);
+            assert!(count(&password, lower) >= 2, "{}", password);
+            assert!(count(&password, upper) >= 3, "{}", password);
+            assert!(count(&password, digits) >= 2, "{}", password);
+            let symbols = password.chars().filter(|c| !c.is_ascii_alphanumeric()).count();
+            assert!(symbols >= 1, "{}", password);
+            if extra == PasswordPolicy::USE_EASY_VISION {
+                assert_eq!(count(&password, "lIOSZ01225"), 0, "{}", password);
+            }
+        }
+    }
+}
+
+#[test]
+fn generate_restricted() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut p = policy();
+    p.flags = PasswordPolicy::USE_SYMBOLS;
+    p.length = 8;
+    p.symbols = "#!".to_string();
+    for _ in 0..100 {
+        assert_eq!(count(&p.generate(&mut rng), "#!"), 8);
+    }
+
+    p.flags = PasswordPolicy::USE_HEX_DIGITS | PasswordPolicy::USE_SYMBOLS;
+    for _ in 0..100 {
+        assert_eq!(count(&p.generate(&mut rng), "0123456789abcdef"), 8);
+    }
+
+    // Minimums exceeding the length extend the password
+    p.flags = PasswordPolicy::USE_DIGITS | PasswordPolicy::USE_UPPERCASE;
+    p.min_digits = 6;
+    p.min_uppercase = 4;
+    let password = p.generate(&mut rng);
+    assert_eq!(password.len(), 10);
+    assert_eq!(count(&password, "0123456789"), 6);
+
+    // Minimums whose sum does not fit the length field
+    p.min_digits = 0xffff;
+    p.min_uppercase = 0xffff;
+    let password = p.generate(&mut rng);
+    assert_eq!(password.len(), 0x1fffe);
+}
@@ -0,0 +1,560 @@
+extern crate pwsafer;
+
+use pwsafer::{PwsafeReader, ReaderError};
+use std::fs::{self, File};
+use std::io::BufReader;
+
+fn open() -> PwsafeReader<BufReader<File>> {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    PwsafeReader::new(file, b"password").unwrap()
+}
+
+#[test]
+fn verify_all() {
+    let mut db = open();
+    db.verify_all().unwrap();
+
+    let mut db = open();
+    db.read_version().unwrap();
+    db.read_field().unwrap();
+    db.verify_all().unwrap();
+    assert!(db.read_field().unwrap().is_none());
+
+    let mut db = open();
+    db.read_version().unwrap();
+    assert!(db.verify().is_err());
+}
+
+#[test]
+fn invalid_password_stops_reading() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut rest = &data[..];
+    match PwsafeReader::new(&mut rest, b"wrong") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+    // Tag, salt, iterations and password hash
+    assert_eq!(data.len() - rest.len(), 4 + 32 + 4 + 32);
+}
+
+#[test]
+fn metadata() {
+    let mut db = open();
+    let metadata = db.metadata().unwrap();
+    assert_eq!(metadata.version, 0x030d);
+    let record = db.read_record().unwrap().unwrap();
+    assert_eq!(record.title(), Some("test"));
+}
+
+#[test]
+fn peek_field_type() {
+    let mut db = open();
+    assert_eq!(db.peek_field_type().unwrap(), Some(0x00));
+    assert_eq!(db.peek_field_type().unwrap(), Some(0x00));
+    db.read_version().unwrap();
+    while let Some(field_type) = db.peek_field_type().unwrap() {
+        assert_eq!(db.read_field().unwrap().unwrap().0, field_type);
+    }
+    assert!(db.read_field().unwrap().is_none());
+    db.verify().unwrap();
+}
+
+#[test]
+fn read_version_out_of_order() {
+    let mut db = open();
+    db.read_field().unwrap();
+    match db.read_version() {
+        Err(ReaderError::OutOfOrder) => (),
+        _ => panic!("expected OutOfOrder"),
+    }
+
+    let mut db = open();
+    assert_eq!(db.read_version().unwrap(), 0x030d);
+    match db.read_version() {
+        Err(ReaderError::OutOfOrder) => (),
+        _ => panic!("expected OutOfOrder"),
+    }
+}
+
+#[test]
+fn from_key() {
+    let db = open();
+    let key = pwsafer::stretch_key(db.get_salt(), db.get_iter(), b"password");
+
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReader::from_key(file, &key).unwrap();
+    db.verify_all().unwrap();
+
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    match PwsafeReader::from_key(file, &[0; 32]) {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[test]
+fn compute_mac() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    let mut fields = Vec::new();
+    while let Some((_, field_data)) = db.read_field().unwrap() {
+        fields.push(field_data);
+    }
+    let mac = db.compute_mac(fields.iter().map(|f| f.as_slice()));
+    assert_eq!(&mac[..], &data[data.len() - 32..]);
+
+    fields[1].push(b'x');
+    assert_ne!(db.compute_mac(fields.iter().map(|f| f.as_slice()))[..], data[data.len() - 32..]);
+}
+
+#[test]
+fn verify_with_progress() {
+    let mut db = open();
+    let total = db.decrypted_len();
+    let mut reported = Vec::new();
+    db.verify_with_progress(|bytes| reported.push(bytes)).unwrap();
+    assert!(!reported.is_empty());
+    assert!(reported.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(*reported.last().unwrap(), total);
+}
+
+#[test]
+fn reject_reserved() {
+    use pwsafer::{PwsafeReaderBuilder, PwsafeWriter};
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+        db.write_field(0x0b, b"filters").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.write_field(0x1a, b"reserved").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+    }
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    db.verify_all().unwrap();
+
+    let mut db = PwsafeReaderBuilder::new()
+        .reject_reserved(true)
+        .open(&buf[..], b"password")
+        .unwrap();
+    db.read_header().unwrap();
+    match db.read_record() {
+        Err(ReaderError::ReservedFieldType(0x1a)) => (),
+        _ => panic!("expected ReservedFieldType"),
+    }
+}
+
+#[cfg(feature = "debug-plaintext")]
+#[test]
+fn decrypted_bytes() {
+    let mut db = open();
+    let plaintext = db.decrypted_bytes().to_vec();
+    assert_eq!(plaintext.len() as u64, db.decrypted_len());
+    assert_eq!(plaintext.len() % 16, 0);
+    // Version field: length 2, type 0, version 0x030d
+    assert_eq!(&plaintext[..7], &[2, 0, 0, 0, 0x00, 0x0d, 0x03]);
+    db.verify_all().unwrap();
+    assert_eq!(db.decrypted_bytes(), &plaintext[..]);
+}
+
+#[test]
+fn field_lengths_at_block_boundaries() {
+    use pwsafer::PwsafeWriter;
+
+    // Every length up to four blocks, then long fields
+    let fields: Vec<Vec<u8>> = (0..=64)
+        .chain(vec![1000, 65536 + 11])
+        .map(|len| (0..len).map(|i| (i % 251) as u8 + 1).collect())
+        .collect();
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        for data in &fields {
+            db.write_field(0x05, data).unwrap();
+        }
+        db.finish().unwrap();
+    }
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    for data in &fields {
+        let (field_type, read) = db.read_field().unwrap().unwrap();
+        assert_eq!(field_type, 0x05);
+        assert_eq!(&read, data, "field of length {}", data.len());
+    }
+    assert!(db.read_field().unwrap().is_none());
+    db.verify().unwrap();
+}
+
+#[test]
+fn open_reads_header() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let (header, mut db) = pwsafer::open(file, b"password").unwrap();
+    assert_eq!(header.version(), Some(0x030d));
+    assert_eq!(db.read_record().unwrap().unwrap().title(), Some("test"));
+    assert!(db.read_record().unwrap().is_none());
+    db.verify().unwrap();
+
+    let data = fs::read("tests/corpus/missing_version.psafe3").unwrap();
+    match pwsafer::open(&data[..], b"password") {
+        Err(ReaderError::InvalidHeader) => (),
+        _ => panic!("expected InvalidHeader"),
+    }
+}
+
+#[test]
+fn typed_fields() {
+    use pwsafer::{PwsafeHeaderField, PwsafeRecordField};
+
+    let mut db = open();
+    let header: Vec<PwsafeHeaderField> = db.typed_header_fields().map(|f| f.unwrap()).collect();
+    assert_eq!(header[0], PwsafeHeaderField::Version(0x030d));
+    assert!(!header.contains(&PwsafeHeaderField::EndOfHeader));
+
+    let fields: Vec<PwsafeRecordField> = db.typed_fields().map(|f| f.unwrap()).collect();
+    assert!(fields.contains(&PwsafeRecordField::Title("test".to_string())));
+    assert_eq!(fields.last(), Some(&PwsafeRecordField::EndOfRecord));
+    db.verify().unwrap();
+
+    let data = fs::read("tests/corpus/non_utf8.psafe3").unwrap();
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    db.typed_header_fields().for_each(|f| {
+        f.unwrap();
+    });
+    let results: Vec<_> = db.typed_fields().collect();
+    assert!(matches!(results.last(), Some(Err(ReaderError::FieldError(_)))));
+}
+
+#[test]
+fn empty_field() {
+    use pwsafer::{PwsafeRecord, PwsafeRecordField, PwsafeWriter};
+
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Title("empty notes".to_string()),
+        PwsafeRecordField::Notes(String::new()),
+        PwsafeRecordField::Password(String::new()),
+    ]);
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_record(&record).unwrap();
+        db.finish().unwrap();
+    }
+
+    let (_, mut db) = pwsafer::open(&buf[..], b"password").unwrap();
+    let read = db.read_record().unwrap().unwrap();
+    assert_eq!(read.fields(), record.fields());
+    assert!(db.read_record().unwrap().is_none());
+    db.verify().unwrap();
+
+    // Empty fields take exactly one block and contribute nothing to the HMAC
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    let mut fields = Vec::new();
+    while let Some((field_type, data)) = db.read_field().unwrap() {
+        if field_type == 0x05 {
+            assert!(data.is_empty());
+        }
+        fields.push(data);
+    }
+    let mac = db.compute_mac(fields.iter().filter(|f| !f.is_empty()).map(|f| &f[..]));
+    assert_eq!(mac, db.compute_mac(fields.iter().map(|f| &f[..])));
+    db.verify().unwrap();
+}
+
+#[test]
+fn field_type_histogram() {
+    let mut db = open();
+    let histogram = db.field_type_histogram().unwrap();
+    assert_eq!(histogram.get(&0x00), Some(&1));
+    assert_eq!(histogram.get(&0x03), Some(&1));
+    assert_eq!(histogram.get(&0xff), Some(&2));
+    assert_eq!(histogram.get(&0xdf), None);
+    db.verify().unwrap();
+    assert!(db.field_type_histogram().unwrap().is_empty());
+}
+
+#[test]
+fn new_str() {
+    use pwsafer::PwsafeWriter;
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, "pässwörd".as_bytes()).unwrap();
+        db.write_empty_header().unwrap();
+        db.finish().unwrap();
+    }
+    PwsafeReader::new_str(&buf[..], "pässwörd").unwrap().verify_all().unwrap();
+    // The same password in Latin-1
+    match PwsafeReader::new(&buf[..], b"p\xe4ssw\xf6rd") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[test]
+fn clear_buffer() {
+    use pwsafer::PwsafeReaderBuilder;
+
+    let mut db = open();
+    db.read_header().unwrap();
+    db.clear_buffer();
+    assert_eq!(db.decrypted_len(), 0);
+    assert!(db.read_record().unwrap().is_none());
+
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReaderBuilder::new().clear_on_eof(true).open(file, b"password").unwrap();
+    let len = db.decrypted_len();
+    while db.read_field().unwrap().is_some() {
+        assert_eq!(db.decrypted_len(), len);
+    }
+    assert_eq!(db.decrypted_len(), 0);
+    db.verify().unwrap();
+}
+
+#[test]
+fn read_field_verified() {
+    let mut db = open();
+    let mut count = 0;
+    while db.read_field_verified().unwrap().is_some() {
+        count += 1;
+    }
+    assert!(count > 0);
+
+    let data = fs::read("tests/corpus/bad_mac.psafe3").unwrap();
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    let err = loop {
+        match db.read_field_verified() {
+            Ok(Some(_)) => (),
+            Ok(None) => panic!("expected MacError"),
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(err, ReaderError::MacError(_)));
+}
+
+#[test]
+fn fields() {
+    let mut db = open();
+    let fields: Vec<(u8, Vec<u8>)> = db.fields().collect::<Result<_, _>>().unwrap();
+    assert_eq!(fields[0], (0x00, vec![0x0d, 0x03]));
+    assert_eq!(fields.last().unwrap().0, 0xff);
+    assert!(db.fields().next().is_none());
+    db.verify().unwrap();
+
+    let data = fs::read("tests/corpus/giant_length.psafe3").unwrap();
+    let mut db = PwsafeReader::new(&data[..], b"password").unwrap();
+    let results: Vec<_> = db.fields().collect();
+    assert!(matches!(results.last(), Some(Err(ReaderError::InvalidFieldLength))));
+}
+
+#[test]
+fn max_iterations() {
+    use pwsafer::PwsafeReaderBuilder;
+
+    let mut data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let builder = PwsafeReaderBuilder::new().max_iterations(2048);
+    builder.open(&data[..], b"password").unwrap();
+
+    // A hostile iteration count is refused before any key stretching
+    data[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+    match builder.open(&data[..], b"password") {
+        Err(ReaderError::ExcessiveIterations(iter)) => assert_eq!(iter, u32::MAX),
+        _ => panic!("expected ExcessiveIterations"),
+    }
+
+    let mut calls = Vec::new();
+    let res = PwsafeReaderBuilder::new().open_with_progress(&data[..], b"password", |done, total| {
+        calls.push((done, total));
+        calls.len() < 3
+    });
+    assert!(matches!(res, Err(ReaderError::Cancelled)));
+    assert_eq!(calls, vec![(0, u32::MAX), (65536, u32::MAX), (131072, u32::MAX)]);
+}
+
+#[test]
+fn stretch_key_with_progress() {
+    let mut db = open();
+    let key = pwsafer::stretch_key(db.get_salt(), 200_000, b"password");
+    let mut calls = 0;
+    let res = pwsafer::stretch_key_with_progress(db.get_salt(), 200_000, b"password", |_| {
+        calls += 1;
+        true
+    });
+    assert_eq!(res, Some(key));
+    assert_eq!(calls, 4);
+    db.verify_all().unwrap();
+}
+
+#[test]
+fn same_key() {
+    let salt = [7; 32];
+    assert!(pwsafer::same_key(&salt, 2048, b"password", b"password"));
+    assert!(!pwsafer::same_key(&salt, 2048, b"password", b"Password"));
+    assert!(!pwsafer::same_key(&salt, 2048, b"", b"password"));
+}
+
+#[test]
+fn next_database() {
+    use pwsafer::PwsafeWriter;
+
+    let mut data = fs::read("tests/pwsafe.psafe3").unwrap();
+    {
+        let mut db = PwsafeWriter::new(&mut data, 2048, b"secret").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_field(0x03, b"second").unwrap();
+        db.write_field(0xff, b"").unwrap();
+        db.finish().unwrap();
+    }
+
+    let (_, mut db) = pwsafer::open(&data[..], b"password").unwrap();
+    assert_eq!(db.read_record().unwrap().unwrap().title(), Some("test"));
+    assert!(db.read_record().unwrap().is_none());
+    db.verify().unwrap();
+
+    let mut db = db.next_database(b"secret").unwrap().unwrap();
+    db.read_header().unwrap();
+    assert_eq!(db.read_record().unwrap().unwrap().title(), Some("second"));
+    db.verify().unwrap();
+    assert!(db.next_database(b"secret").unwrap().is_none());
+}
+
+#[test]
+fn visit_fields() {
+    let mut expected = Vec::new();
+    let mut db = open();
+    while let Some(field) = db.read_field().unwrap() {
+        expected.push(field);
+    }
+
+    let mut visited = Vec::new();
+    let mut db = open();
+    db.visit_fields(|field_type, data| {
+        visited.push((field_type, data.to_vec()));
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(visited, expected);
+    db.verify().unwrap();
+
+    // Errors of the closure stop the visit
+    let mut db = open();
+    let mut count = 0;
+    let res = db.visit_fields(|_, _| {
+        count += 1;
+        if count == 2 {
+            return Err(ReaderError::InvalidRecord);
+        }
+        Ok(())
+    });
+    assert!(matches!(res, Err(ReaderError::InvalidRecord)));
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn from_reader_exact() {
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    for len in &[data.len() as u64, 0, 100, u64::MAX] {
+        let mut db = PwsafeReader::from_reader_exact(&data[..], b"password", *len).unwrap();
+        db.verify_all().unwrap();
+    }
+}
+
+#[test]
+fn strict() {
+    use pwsafer::{PwsafeReaderBuilder, PwsafeWriter};
+
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReaderBuilder::new().strict(true).open(file, b"password").unwrap();
+    db.verify_all().unwrap();
+
+    let write = |header_type: u8, record_type: u8| {
+        let mut buf = Vec::new();
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+        db.write_field(header_type, b"header").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.write_field(record_type, b"record").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+        drop(db);
+        buf
+    };
+    for &(header_type, record_type, unknown) in &[(0x30, 0x03, 0x30), (0x09, 0x7f, 0x7f), (0x09, 0x1a, 0x1a)] {
+        let buf = write(header_type, record_type);
+        PwsafeReader::new(&buf[..], b"password").unwrap().verify_all().unwrap();
+        let mut db = PwsafeReaderBuilder::new().strict(true).open(&buf[..], b"password").unwrap();
+        match db.verify_all() {
+            Err(ReaderError::UnknownFieldType(t)) if t == unknown => (),
+            res => panic!("expected UnknownFieldType, got {:?}", res),
+        }
+
+        // Peeking fails on the same field
+        let mut db = PwsafeReaderBuilder::new().strict(true).open(&buf[..], b"password").unwrap();
+        loop {
+            match db.peek_field_type() {
+                Ok(_) => db.read_field().unwrap(),
+                Err(ReaderError::UnknownFieldType(t)) if t == unknown => break,
+                res => panic!("expected UnknownFieldType, got {:?}", res),
+            };
+        }
+    }
+}
+
+#[test]
+fn new_interruptible() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let data = fs::read("tests/pwsafe.psafe3").unwrap();
+    let cancel = AtomicBool::new(false);
+    let mut db = PwsafeReader::new_interruptible(&data[..], b"password", &cancel).unwrap();
+    db.verify_all().unwrap();
+
+    cancel.store(true, Ordering::Relaxed);
+    match PwsafeReader::new_interruptible(&data[..], b"password", &cancel) {
+        Err(ReaderError::Cancelled) => (),
+        _ => panic!("expected Cancelled"),
+    }
+}
+
+#[test]
+fn fields_read() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReader::new(file, b"password").unwrap();
+    assert_eq!(db.fields_read(), 0);
+    db.read_field().unwrap();
+    assert_eq!(db.fields_read(), 1);
+    let mut count = 1;
+    db.visit_fields(|_, _| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(db.fields_read(), count);
+    assert!(db.read_field().unwrap().is_none());
+    assert_eq!(db.fields_read(), count);
+    db.verify().unwrap();
+}
+
+#[test]
+fn all_fields() {
+    use pwsafer::{AnyField, PwsafeHeaderField, PwsafeRecordField};
+
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReader::new(file, b"password").unwrap();
+    let fields: Vec<AnyField> = db.all_fields().map(|f| f.unwrap()).collect();
+    db.verify().unwrap();
+
+    assert!(matches!(fields[0], AnyField::Header(PwsafeHeaderField::Version(_))));
+    let end = fields
+        .iter()
+        .position(|f| *f == AnyField::Header(PwsafeHeaderField::EndOfHeader))
+        .unwrap();
+    assert!(fields[..end].iter().all(|f| matches!(f, AnyField::Header(_))));
+    assert!(fields[end + 1..].iter().all(|f| matches!(f, AnyField::Record(_))));
+    assert_eq!(fields.last(), Some(&AnyField::Record(PwsafeRecordField::EndOfRecord)));
+    assert!(fields[0].to_string().starts_with("Version: 0x03"));
+}
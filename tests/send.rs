@@ -0,0 +1,28 @@
+extern crate pwsafer;
+
+use pwsafer::{PwsafeDatabase, PwsafeReader, PwsafeWriter, ReaderError};
+use std::fs::File;
+use std::io::BufReader;
+use std::thread;
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+
+#[test]
+fn send_sync() {
+    assert_send::<PwsafeReader<BufReader<File>>>();
+    assert_sync::<PwsafeReader<BufReader<File>>>();
+    assert_send::<PwsafeWriter<File>>();
+    assert_sync::<PwsafeWriter<File>>();
+    assert_send::<PwsafeDatabase>();
+    assert_sync::<PwsafeDatabase>();
+    assert_send::<ReaderError>();
+    assert_sync::<ReaderError>();
+}
+
+#[test]
+fn read_on_worker_thread() {
+    let file = BufReader::new(File::open("tests/pwsafe.psafe3").unwrap());
+    let mut db = PwsafeReader::new(file, b"password").unwrap();
+    thread::spawn(move || db.verify_all().unwrap()).join().unwrap();
+}
@@ -0,0 +1,25 @@
+#![cfg(feature = "test-vectors")]
+
+extern crate pwsafer;
+extern crate rand;
+
+use pwsafer::{PwsafeReader, PwsafeWriter};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[test]
+fn key_material() {
+    let mut buf = Vec::new();
+    let keys = {
+        let rng = StdRng::seed_from_u64(42);
+        let mut db = PwsafeWriter::with_rng(&mut buf, 2048, b"password", rng).unwrap();
+        db.write_empty_header().unwrap();
+        db.finish().unwrap();
+        db.key_material().clone()
+    };
+    // The IV is stored in plaintext right before the encrypted fields
+    assert_eq!(&buf[136..152], &keys.iv);
+
+    let db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert_eq!(db.key_material(), &keys);
+}
@@ -0,0 +1,18 @@
+#![cfg(feature = "uuid")]
+
+extern crate pwsafer;
+extern crate uuid;
+
+use pwsafer::{PwsafeDatabase, PwsafeRecord, PwsafeRecordField};
+use uuid::Uuid;
+
+#[test]
+fn typed_uuids() {
+    let (db, uuid) = PwsafeDatabase::create();
+    assert_eq!(db.header().database_uuid_typed(), Some(Uuid::from_bytes(uuid)));
+
+    let record = PwsafeRecord::new(vec![PwsafeRecordField::Uuid([1; 16])]);
+    assert_eq!(record.uuid(), Some([1; 16]));
+    assert_eq!(record.uuid_typed(), Some(Uuid::from_bytes([1; 16])));
+    assert_eq!(PwsafeRecord::new(Vec::new()).uuid_typed(), None);
+}
@@ -0,0 +1,701 @@
+extern crate block_modes;
+extern crate hmac;
+extern crate pwsafer;
+extern crate rand;
+extern crate sha2;
+extern crate twofish;
+
+use block_modes::block_padding::ZeroPadding;
+use block_modes::cipher::NewBlockCipher;
+use block_modes::{BlockMode, Cbc, Ecb};
+use hmac::{Hmac, Mac, NewMac};
+use pwsafer::{PwsafeHeaderField, PwsafeReader, PwsafeReaderBuilder, PwsafeWriter, ReaderError};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+use twofish::Twofish;
+
+const FIELDS: &[(u8, &[u8])] = &[
+    (0x00, &[0x0e, 0x03]),
+    (0xff, &[]),
+    (0x03, b"a title"),
+    (0x05, b"some notes spanning more than a single block of the database"),
+    (0x06, b"elevenbytes"),
+    (0xff, &[]),
+];
+
+/// Writes a database the way the writer did before fields were streamed: all field blocks are
+/// buffered and encrypted at once.
+fn buffered_reference(seed: u64, iter: u32, password: &[u8], fields: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut out = b"PWS3".to_vec();
+    let mut salt = [0u8; 32];
+    rng.fill_bytes(&mut salt);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&iter.to_le_bytes());
+
+    let mut hasher = Sha256::default();
+    hasher.update(password);
+    hasher.update(salt);
+    let mut key = hasher.finalize();
+    for _ in 0..iter {
+        key = Sha256::digest(&key);
+    }
+    out.extend_from_slice(&Sha256::digest(&key));
+
+    let mut k = [0u8; 32];
+    let mut l = [0u8; 32];
+    let mut iv = [0u8; 16];
+    rng.fill_bytes(&mut k);
+    rng.fill_bytes(&mut l);
+    rng.fill_bytes(&mut iv);
+    let twofish_cipher = Twofish::new_from_slice(&key).unwrap();
+    for subkey in &[k, l] {
+        let mut subkey = *subkey;
+        let ecb_cipher = Ecb::<&Twofish, ZeroPadding>::new(&twofish_cipher, &Default::default());
+        ecb_cipher.encrypt(&mut subkey, 32).unwrap();
+        out.extend_from_slice(&subkey);
+    }
+    out.extend_from_slice(&iv);
+
+    let mut hmac = Hmac::<Sha256>::new_from_slice(&l).unwrap();
+    let mut buffer = Vec::new();
+    for (field_type, data) in fields {
+        hmac.update(data);
+        let mut encoded = (data.len() as u32).to_le_bytes().to_vec();
+        encoded.push(*field_type);
+        encoded.extend_from_slice(data);
+        for chunk in encoded.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            rng.fill_bytes(&mut block[chunk.len()..]);
+            buffer.extend_from_slice(&block);
+        }
+    }
+    let pos = buffer.len();
+    let cbc_cipher = Cbc::<Twofish, ZeroPadding>::new_from_slices(&k, &iv).unwrap();
+    cbc_cipher.encrypt(&mut buffer, pos).unwrap();
+    out.extend_from_slice(&buffer);
+    out.extend_from_slice(b"PWS3-EOFPWS3-EOF");
+    out.extend_from_slice(&hmac.finalize().into_bytes());
+    out
+}
+
+#[test]
+fn empty_database() {
+    let mut buf = Vec::new();
+    let uuid = {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        let uuid = db.write_empty_header().unwrap();
+        db.finish().unwrap();
+        uuid
+    };
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030e);
+    let (field_type, data) = db.read_field().unwrap().unwrap();
+    match PwsafeHeaderField::new(field_type, data).unwrap() {
+        PwsafeHeaderField::Uuid(u) => assert_eq!(u, uuid),
+        field => panic!("unexpected field {:?}", field),
+    }
+    assert_eq!(uuid[6] >> 4, 4);
+    let (field_type, data) = db.read_field().unwrap().unwrap();
+    assert_eq!(field_type, 0xff);
+    assert!(data.is_empty());
+    assert!(db.read_field().unwrap().is_none());
+    db.verify().unwrap();
+}
+
+#[test]
+fn streamed_matches_buffered() {
+    let mut buf = Vec::new();
+    {
+        let rng = StdRng::seed_from_u64(42);
+        let mut db = PwsafeWriter::with_rng(&mut buf, 2048, b"password", rng).unwrap();
+        for (field_type, data) in FIELDS {
+            db.write_field(*field_type, data).unwrap();
+        }
+        db.finish().unwrap();
+    }
+    assert_eq!(buf, buffered_reference(42, 2048, b"password", FIELDS));
+}
+
+#[test]
+fn fields_are_written_immediately() {
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+        db.write_field(0x05, &[0u8; 40]).unwrap();
+    }
+    // Preamble, one block for the version and three for the notes
+    assert_eq!(buf.len(), 152 + 4 * 16);
+}
+
+#[test]
+fn custom_eof_sentinel() {
+    let sentinel = *b"TEST-EOFTEST-EOF";
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.set_eof_sentinel(sentinel);
+        db.write_empty_header().unwrap();
+        db.finish().unwrap();
+    }
+    assert_eq!(&buf[buf.len() - 48..buf.len() - 32], &sentinel);
+
+    match PwsafeReader::new(&buf[..], b"password") {
+        Err(ReaderError::MissingEof) => (),
+        _ => panic!("expected MissingEof"),
+    }
+    let mut db = PwsafeReaderBuilder::new()
+        .eof_sentinel(sentinel)
+        .open(&buf[..], b"password")
+        .unwrap();
+    assert_eq!(db.read_version().unwrap(), 0x030e);
+    db.verify_all().unwrap();
+    assert_eq!(pwsafer::EOF_SENTINEL, b"PWS3-EOFPWS3-EOF");
+}
+
+#[test]
+fn finish_only_once() {
+    let mut buf = Vec::new();
+    let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+    db.write_empty_header().unwrap();
+    db.finish().unwrap();
+    assert!(db.finish().is_err());
+    assert!(db.write_field(0x03, b"late").is_err());
+}
+
+#[test]
+fn from_key() {
+    let salt = [7u8; 32];
+    let key = pwsafer::stretch_key(&salt, 2048, b"password");
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::from_key(&mut buf, 2048, &salt, &key).unwrap();
+        db.write_empty_header().unwrap();
+        db.finish().unwrap();
+    }
+    assert_eq!(&buf[4..36], &salt);
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert_eq!(db.get_salt(), &salt);
+    db.verify_all().unwrap();
+}
+
+/// Copies a database the way converters do, mixing reader and writer results with `?`.
+fn copy(data: &[u8], password: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut out = Vec::new();
+    let mut rdb = PwsafeReader::new(data, password)?;
+    let mut wdb = PwsafeWriter::new(&mut out, rdb.get_iter(), b"new")?;
+    while let Some((field_type, field_data)) = rdb.read_field()? {
+        wdb.write_field(field_type, &field_data)?;
+    }
+    rdb.verify()?;
+    wdb.finish()?;
+    drop(wdb);
+    Ok(out)
+}
+
+#[test]
+fn copy_with_question_mark() {
+    let data = std::fs::read("tests/pwsafe.psafe3").unwrap();
+    let out = copy(&data, b"password").unwrap();
+    PwsafeReader::new(&out[..], b"new").unwrap().verify_all().unwrap();
+
+    let err = copy(&data, b"wrong").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Invalid password");
+
+    let err: std::io::Error = PwsafeHeaderField::new(0x00, vec![0]).unwrap_err().into();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn write_record_sorts_fields() {
+    use pwsafer::{PwsafeRecord, PwsafeRecordField};
+
+    let mut record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Blob {
+            field_type: 0x7f,
+            data: b"unknown".to_vec(),
+        },
+        PwsafeRecordField::Password("secret".to_string()),
+        PwsafeRecordField::Title("title".to_string()),
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Group("group".to_string()),
+    ]);
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_record(&record).unwrap();
+        db.finish().unwrap();
+    }
+
+    record.sort_fields();
+    let types: Vec<u8> = record.fields().iter().map(|f| f.field_type()).collect();
+    assert_eq!(types, vec![0x01, 0x02, 0x03, 0x06, 0x7f]);
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    db.read_header().unwrap();
+    assert_eq!(db.read_record().unwrap().unwrap(), record);
+    assert!(db.read_record().unwrap().is_none());
+    db.verify().unwrap();
+}
+
+#[cfg(feature = "debug-hmac")]
+#[test]
+fn hmac_snapshots() {
+    let mut buf = Vec::new();
+    let expected = {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        for &(field_type, data) in FIELDS {
+            db.write_field(field_type, data).unwrap();
+        }
+        db.finish().unwrap();
+        db.hmac_snapshots().to_vec()
+    };
+    assert_eq!(expected.len(), FIELDS.len());
+
+    // Corrupt the second block of the notes field, the snapshots diverge from there on
+    let notes = 152 + 3 * 16;
+    buf[notes + 16 + 8] ^= 0x01;
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert!(db.verify_all().is_err());
+    let first_diff = db
+        .hmac_snapshots()
+        .iter()
+        .zip(&expected)
+        .position(|(a, b)| a != b);
+    assert_eq!(first_diff, Some(3));
+}
+
+#[test]
+fn write_built_record() {
+    use pwsafer::{FieldError, PwsafeRecordBuilder, PwsafeRecordField, RecordFieldType};
+
+    let record = PwsafeRecordBuilder::new()
+        .title("Mail")
+        .username("alice")
+        .password("secret")
+        .group("Internet.Mail")
+        .url("https://mail.example")
+        .build()
+        .unwrap();
+    assert!(matches!(record.fields()[0], PwsafeRecordField::Uuid(_)));
+    assert!(record.fields().iter().any(|f| matches!(f, PwsafeRecordField::CreationTime(t) if *t > 0)));
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.write_record(&record).unwrap();
+        db.finish().unwrap();
+    }
+    let db = pwsafer::PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    assert_eq!(db.records(), &[record]);
+
+    match PwsafeRecordBuilder::new().title("no password").build() {
+        Err(FieldError::MissingField(RecordFieldType::Password)) => (),
+        _ => panic!("expected MissingField"),
+    }
+}
+
+/// Accepts a limited number of bytes, then fails.
+struct FailingWriter {
+    written: Vec<u8>,
+    limit: usize,
+}
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.limit - self.written.len());
+        if n == 0 {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.written.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_errors_are_immediate() {
+    let inner = FailingWriter {
+        written: Vec::new(),
+        limit: 152 + 40,
+    };
+    let mut db = PwsafeWriter::new(inner, 2048, b"password").unwrap();
+    db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+    let err = db.write_field(0x05, &[0u8; 40]).unwrap_err();
+    assert_eq!(err.to_string(), "disk full");
+    // The failed field is lost, later calls must not produce a database with a gap
+    assert!(db.write_field(0xff, &[]).is_err());
+    assert!(db.finish().is_err());
+}
+
+#[test]
+fn copy_errors_fail_the_writer() {
+    let mut data = std::fs::read("tests/pwsafe.psafe3").unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    let mut reader = PwsafeReader::new(&data[..], b"password").unwrap();
+    let mut buf = Vec::new();
+    let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+    assert!(db.copy_fields(&mut reader).is_err());
+    // Neither finished nor silently dropped
+    assert!(db.finish().is_err());
+
+    let db = PwsafeWriter::new(Vec::new(), 2048, b"password").unwrap();
+    db.abandon();
+}
+
+#[test]
+fn with_salt() {
+    let write = || {
+        let mut buf = Vec::new();
+        {
+            let mut db = PwsafeWriter::with_salt(&mut buf, 2048, b"password", [3; 32]).unwrap();
+            db.write_empty_header().unwrap();
+            db.finish().unwrap();
+        }
+        buf
+    };
+    let (a, b) = (write(), write());
+    // Tag, salt, iterations and password hash are identical, the keys are not
+    assert_eq!(&a[..72], &b[..72]);
+    assert_ne!(&a[72..], &b[72..]);
+    assert_eq!(&a[4..36], &[3; 32]);
+    PwsafeReader::new(&a[..], b"password").unwrap().verify_all().unwrap();
+
+    let seeded = || {
+        let mut buf = Vec::new();
+        {
+            let rng = StdRng::seed_from_u64(1);
+            let mut db = PwsafeWriter::with_rng(&mut buf, 2048, b"password", rng).unwrap();
+            db.write_empty_header().unwrap();
+            db.finish().unwrap();
+        }
+        buf
+    };
+    assert_eq!(seeded(), seeded());
+
+    let salted = || {
+        let mut buf = Vec::new();
+        {
+            let rng = StdRng::seed_from_u64(1);
+            let mut db = PwsafeWriter::with_salt_and_rng(&mut buf, 2048, b"password", [3; 32], rng).unwrap();
+            db.write_empty_header().unwrap();
+            db.finish().unwrap();
+        }
+        buf
+    };
+    let (a, b) = (salted(), salted());
+    assert_eq!(a, b);
+    assert_eq!(&a[4..36], &[3; 32]);
+    PwsafeReader::new(&a[..], b"password").unwrap().verify_all().unwrap();
+}
+
+#[test]
+fn write_record_field() {
+    use pwsafer::{PwsafeDatabase, PwsafeRecordField};
+
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        for (uuid, title) in &[([1; 16], "one"), ([2; 16], "two")] {
+            db.write_record_field(&PwsafeRecordField::Uuid(*uuid)).unwrap();
+            db.write_record_field(&PwsafeRecordField::Title(title.to_string())).unwrap();
+        }
+        db.end_record().unwrap();
+        db.write_record_field(&PwsafeRecordField::Title("no uuid".to_string())).unwrap();
+        db.end_record().unwrap();
+        db.write_record_field(&PwsafeRecordField::Uuid([3; 16])).unwrap();
+        db.write_record_field(&PwsafeRecordField::EndOfRecord).unwrap();
+        db.write_record_field(&PwsafeRecordField::Uuid([4; 16])).unwrap();
+        db.finish().unwrap();
+    }
+    let db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    let fields: Vec<&[PwsafeRecordField]> = db.records().iter().map(|r| r.fields()).collect();
+    assert_eq!(
+        fields,
+        vec![
+            &[PwsafeRecordField::Uuid([1; 16]), PwsafeRecordField::Title("one".to_string())][..],
+            &[PwsafeRecordField::Uuid([2; 16]), PwsafeRecordField::Title("two".to_string())][..],
+            &[PwsafeRecordField::Title("no uuid".to_string())][..],
+            &[PwsafeRecordField::Uuid([3; 16])][..],
+            &[PwsafeRecordField::Uuid([4; 16])][..],
+        ]
+    );
+}
+
+#[test]
+fn rekdf() {
+    let data = std::fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut out = Vec::new();
+    pwsafer::rekdf(&data[..], b"password", &mut out, 4096).unwrap();
+
+    let mut old = PwsafeReader::new(&data[..], b"password").unwrap();
+    let mut new = PwsafeReader::new(&out[..], b"password").unwrap();
+    assert_eq!(old.get_iter(), 2048);
+    assert_eq!(new.get_iter(), 4096);
+    assert_ne!(old.get_salt(), new.get_salt());
+    while let Some(field) = old.read_field().unwrap() {
+        assert_eq!(new.read_field().unwrap(), Some(field));
+    }
+    assert!(new.read_field().unwrap().is_none());
+    new.verify().unwrap();
+
+    assert!(pwsafer::rekdf(&data[..], b"wrong", &mut Vec::new(), 4096).is_err());
+}
+
+#[test]
+fn record_padding() {
+    use pwsafer::{PwsafeDatabase, PwsafeRecord, PwsafeRecordField};
+
+    let write = |password: &str| {
+        let record = PwsafeRecord::new(vec![
+            PwsafeRecordField::Uuid([1; 16]),
+            PwsafeRecordField::Title("title".to_string()),
+            PwsafeRecordField::Password(password.to_string()),
+        ]);
+        let mut buf = Vec::new();
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.set_record_padding(Some(8));
+        db.write_record(&record).unwrap();
+        db.write_record_field(&PwsafeRecordField::Title("field by field".to_string())).unwrap();
+        db.finish().unwrap();
+        drop(db);
+        buf
+    };
+
+    let short = write("x");
+    let long = write("a password that is a lot longer");
+    assert_eq!(short.len(), long.len());
+
+    let db = PwsafeDatabase::read(&short[..], b"password").unwrap();
+    assert_eq!(db.records().len(), 2);
+    for record in db.records() {
+        match record.fields().last() {
+            Some(PwsafeRecordField::Blob { field_type: 0xdf, .. }) => (),
+            field => panic!("expected padding, got {:?}", field),
+        }
+    }
+
+    // Records larger than the padding are left as they are
+    let longer = write(&"x".repeat(200));
+    assert!(longer.len() > long.len());
+    let db = PwsafeDatabase::read(&longer[..], b"password").unwrap();
+    assert_eq!(db.records()[0].fields().len(), 3);
+}
+
+#[test]
+fn block_boundaries_match_buffered() {
+    let data = [0x5au8; 60];
+    let fields: Vec<(u8, &[u8])> = [0, 1, 10, 11, 12, 26, 27, 28, 43, 59]
+        .iter()
+        .map(|&len| (0x05, &data[..len]))
+        .collect();
+    let mut buf = Vec::new();
+    {
+        let rng = StdRng::seed_from_u64(7);
+        let mut db = PwsafeWriter::with_rng(&mut buf, 2048, b"password", rng).unwrap();
+        for (field_type, data) in &fields {
+            db.write_field(*field_type, data).unwrap();
+        }
+        db.finish().unwrap();
+    }
+    assert_eq!(buf, buffered_reference(7, 2048, b"password", &fields));
+}
+
+#[test]
+fn empty_password() {
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"").unwrap();
+        db.write_empty_header().unwrap();
+        db.finish().unwrap();
+    }
+    PwsafeReader::new(&buf[..], b"").unwrap().verify_all().unwrap();
+    match PwsafeReader::new(&buf[..], b"password") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+
+    let data = std::fs::read("tests/pwsafe.psafe3").unwrap();
+    match PwsafeReader::new(&data[..], b"") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
+
+#[test]
+fn record_macs() {
+    use pwsafer::{PwsafeRecord, PwsafeRecordField};
+
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Title("title".to_string()),
+        PwsafeRecordField::Password("secret".to_string()),
+    ]);
+    let mut buf = Vec::new();
+    let key = {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.set_record_macs(true);
+        db.set_record_padding(Some(8));
+        db.write_record(&record).unwrap();
+        db.set_record_macs(false);
+        db.write_record(&record).unwrap();
+        db.finish().unwrap();
+        db.record_mac_key()
+    };
+
+    let mut db = PwsafeReader::new(&buf[..], b"password").unwrap();
+    assert_eq!(db.record_mac_key(), key);
+    db.read_header().unwrap();
+    let mut with_mac = db.read_record().unwrap().unwrap();
+    let without_mac = db.read_record().unwrap().unwrap();
+    db.verify().unwrap();
+    assert_eq!(with_mac.verify_record_mac(&key), Some(true));
+    assert_eq!(without_mac.verify_record_mac(&key), None);
+    assert_eq!(with_mac.verify_record_mac(&[0; 32]), Some(false));
+
+    with_mac.merge_fields(vec![PwsafeRecordField::Password("changed".to_string())]);
+    assert_eq!(with_mac.verify_record_mac(&key), Some(false));
+    with_mac.set_record_mac(&key);
+    assert_eq!(with_mac.verify_record_mac(&key), Some(true));
+}
+
+#[test]
+fn record_macs_survive_rewrite() {
+    use pwsafer::{PwsafeDatabase, PwsafeRecord, PwsafeRecordField};
+
+    let record = PwsafeRecord::new(vec![
+        PwsafeRecordField::Uuid([1; 16]),
+        PwsafeRecordField::Title("title".to_string()),
+    ]);
+    let mut tampered = record.clone();
+    let mut buf = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut buf, 2048, b"password").unwrap();
+        db.write_empty_header().unwrap();
+        db.set_record_macs(true);
+        db.write_record(&record).unwrap();
+        tampered.set_record_mac(&db.record_mac_key());
+        tampered.merge_fields(vec![PwsafeRecordField::Title("changed".to_string())]);
+        for field in tampered.fields() {
+            db.write_record_field(field).unwrap();
+        }
+        db.finish().unwrap();
+    }
+
+    let check = |data: &[u8], password: &[u8], tampered: Option<bool>| {
+        let mut db = PwsafeReader::new(data, password).unwrap();
+        let key = db.record_mac_key();
+        db.read_header().unwrap();
+        assert_eq!(db.read_record().unwrap().unwrap().verify_record_mac(&key), Some(true));
+        assert_eq!(db.read_record().unwrap().unwrap().verify_record_mac(&key), tampered);
+        db.verify().unwrap();
+    };
+    check(&buf, b"password", Some(false));
+
+    let mut db = PwsafeDatabase::read(&buf[..], b"password").unwrap();
+    let mut saved = Vec::new();
+    db.save(&mut saved, b"password", 2048).unwrap();
+    // Only valid MACs are carried over, the tampered record keeps failing
+    check(&saved, b"password", Some(false));
+
+    // Records changed through the API get a new MAC under the key of the database
+    let key = db.record_mac_key();
+    db.records_mut()[1].merge_fields(vec![PwsafeRecordField::Title("edited".to_string())]);
+    db.records_mut()[1].set_record_mac(&key);
+    let mut saved = Vec::new();
+    db.save(&mut saved, b"password", 2048).unwrap();
+    check(&saved, b"password", Some(true));
+
+    let mut rekeyed = Vec::new();
+    pwsafer::rekdf(&buf[..], b"password", &mut rekeyed, 4096).unwrap();
+    check(&rekeyed, b"password", Some(false));
+
+    let mut rotated = Vec::new();
+    let results = pwsafer::rotate_many(vec![(&buf[..], &mut rotated)], b"password", b"new", 2048);
+    assert!(results[0].is_ok());
+    check(&rotated, b"new", Some(false));
+}
+
+#[test]
+fn blobs_round_trip() {
+    use pwsafer::AnyField;
+
+    let mut original = Vec::new();
+    {
+        let mut db = PwsafeWriter::new(&mut original, 2048, b"password").unwrap();
+        db.write_field(0x00, &[0x0e, 0x03]).unwrap();
+        db.write_blob(0x30, b"future header field").unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.write_field(0x03, b"title").unwrap();
+        db.write_blob(0x7f, &[0, 1, 2, 0xff]).unwrap();
+        db.write_field(0xff, &[]).unwrap();
+        db.finish().unwrap();
+    }
+
+    // Copy through the typed fields
+    let mut copy = Vec::new();
+    {
+        let mut rdb = PwsafeReader::new(&original[..], b"password").unwrap();
+        let mut wdb = PwsafeWriter::new(&mut copy, 2048, b"password").unwrap();
+        for field in rdb.all_fields() {
+            match field.unwrap() {
+                AnyField::Header(field) => wdb.write_header_field(&field).unwrap(),
+                AnyField::Record(field) => wdb.write_record_field(&field).unwrap(),
+            }
+        }
+        rdb.verify().unwrap();
+        wdb.finish().unwrap();
+    }
+
+    let raw = |data: &[u8]| {
+        let mut db = PwsafeReader::new(data, b"password").unwrap();
+        let fields: Vec<(u8, Vec<u8>)> = db.fields().map(|f| f.unwrap()).collect();
+        db.verify().unwrap();
+        fields
+    };
+    let fields = raw(&copy);
+    assert_eq!(fields, raw(&original));
+    assert!(fields.contains(&(0x30, b"future header field".to_vec())));
+    assert!(fields.contains(&(0x7f, vec![0, 1, 2, 0xff])));
+}
+
+#[test]
+fn rotate_many() {
+    let data = std::fs::read("tests/pwsafe.psafe3").unwrap();
+    let mut tampered = data.clone();
+    let len = tampered.len();
+    tampered[len - 1] ^= 1;
+
+    let mut out = [Vec::new(), Vec::new(), Vec::new()];
+    let results = {
+        let sources = [&data[..], &tampered[..], &data[..]];
+        let files = sources.iter().cloned().zip(out.iter_mut());
+        pwsafer::rotate_many(files, b"password", b"new", 2048)
+    };
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+
+    for i in &[0, 2] {
+        PwsafeReader::new(&out[*i][..], b"new").unwrap().verify_all().unwrap();
+    }
+    match PwsafeReader::new(&out[0][..], b"password") {
+        Err(ReaderError::InvalidPassword) => (),
+        _ => panic!("expected InvalidPassword"),
+    }
+}
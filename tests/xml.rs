@@ -0,0 +1,20 @@
+#![cfg(feature = "xml")]
+
+extern crate pwsafer;
+
+use pwsafer::PwsafeDatabase;
+
+#[test]
+fn filters_xml_well_formed() {
+    let (mut db, _) = PwsafeDatabase::create();
+    assert_eq!(db.header().filters_xml_well_formed(), None);
+
+    let xml = "<?xml version=\"1.0\"?>\n<filters><filter filtername=\"Mail\"/></filters>\n";
+    db.header_mut().set_filters_xml(Some(xml.to_string()));
+    assert_eq!(db.header().filters_xml_well_formed(), Some(true));
+
+    for xml in &["", "<filters>", "<filters></filter>", "</filters>", "<a/><b/>", "x<a/>", "<a b=1/>", "<a b='1' b='2'/>"] {
+        db.header_mut().set_filters_xml(Some(xml.to_string()));
+        assert_eq!(db.header().filters_xml_well_formed(), Some(false), "{}", xml);
+    }
+}